@@ -0,0 +1,198 @@
+use std::fs::File;
+use std::io::Write;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::market::{LogFormat, ZseConfig, ZseStats};
+use crate::registry::MarketRegistry;
+use crate::trader_core::{strategy_by_name, TradeCounts, Trader, STRATEGIES};
+
+//ticks a strategy gets to move the day clock before the run is called off
+const MAX_TICKS_PER_DAY: u32 = 1000;
+
+/// EUR value of the portfolio at the end of a market day.
+pub struct EquityPoint {
+    pub day: u32,
+    /// EUR USD YEN YUAN
+    pub holdings: Vec<f32>,
+    pub total: f32,
+    /// what our own market counted during the day, None if it isn't in the registry
+    pub zse: Option<ZseStats>,
+    /// share of each good in ZSE's unlocked inventory at the end of the day, EUR USD YEN YUAN
+    pub treasury: Option<[f32; 4]>,
+}
+
+pub struct BacktestReport {
+    pub strategy: String,
+    pub days: u32,
+    pub start: f32,
+    pub quantities: Vec<f32>,
+    pub curve: Vec<EquityPoint>,
+    pub counts: TradeCounts,
+    /// events the bus handed to single markets, echoes it dropped and deliveries still waiting for a busy market
+    pub deliveries: u64,
+    pub echoes: u64,
+    pub pending: usize,
+}
+
+impl BacktestReport {
+    pub fn end(&self) -> f32 {
+        self.curve.last().map_or(self.start, |p| p.total)
+    }
+
+    /// Largest fall from a previous peak, in EUR and relative to that peak.
+    pub fn max_drawdown(&self) -> (f32, f32) {
+        let mut peak = self.start;
+        let mut worst = (0.0, 0.0);
+        for p in &self.curve {
+            peak = peak.max(p.total);
+            let drop = peak - p.total;
+            if drop > worst.0 {
+                worst = (drop, if peak > 0.0 { drop / peak } else { 0.0 });
+            }
+        }
+        worst
+    }
+
+    pub fn print(&self) {
+        let names = ["EUR", "USD", "YEN", "YUAN"];
+        let pnl = self.end() - self.start;
+        println!("Strategy: {}, {} market days", self.strategy, self.days);
+        println!("Budget: {} EUR -> {} EUR", self.start, self.end());
+        println!("PnL: {} EUR ({:.2}%)", pnl, 100.0 * pnl / self.start);
+
+        println!("\nHoldings\tquantity\tEUR");
+        let last = self.curve.last().map(|p| p.holdings.clone()).unwrap_or_default();
+        for i in 0..4 {
+            println!("{}:\t{}\t{}", names[i], self.quantities[i], last.get(i).copied().unwrap_or_default());
+        }
+
+        let c = &self.counts;
+        println!("\nLocks: {} buy, {} sell", c.lock_buy, c.lock_sell);
+        println!("Fills: {} buy, {} sell", c.buy, c.sell);
+        println!("Refused: {}, waits: {}", c.refused, c.wait);
+        println!("Fill rate: {:.1}%", 100.0 * c.fill_rate());
+
+        let (drawdown, relative) = self.max_drawdown();
+        println!("Max drawdown: {} EUR ({:.2}%)", drawdown, 100.0 * relative);
+
+        if let Some(last) = self.curve.last().and_then(|p| p.zse.as_ref()) {
+            let fees: f32 = self.curve.iter().filter_map(|p| p.zse.as_ref()).map(|s| s.fee_income).sum();
+            println!("\nZSE fees: {} EUR collected, fee account holds {} EUR", fees, last.fee_account);
+        }
+        if let Some(weights) = self.curve.last().and_then(|p| p.treasury) {
+            let shares: Vec<String> = (0..4).map(|i| format!("{} {:.1}%", names[i], 100.0 * weights[i])).collect();
+            println!("ZSE treasury: {}", shares.join(", "));
+        }
+        println!("\nBus: {} deliveries, {} echoes dropped, {} pending", self.deliveries, self.echoes, self.pending);
+    }
+
+    pub fn write_equity(&self, path: &str) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        writeln!(file, "day,eur,usd,yen,yuan,total,zse_fees,zse_eur,zse_usd,zse_yen,zse_yuan")?;
+        for p in &self.curve {
+            let h = &p.holdings;
+            //ZSE columns stay empty without it, its goods are shares of its treasury
+            let fees = p.zse.as_ref().map(|s| s.fee_income.to_string()).unwrap_or_default();
+            let shares: Vec<String> = (0..4).map(|i| p.treasury.map(|w| w[i].to_string()).unwrap_or_default()).collect();
+            writeln!(file, "{},{},{},{},{},{},{},{}", p.day, h[0], h[1], h[2], h[3], p.total, fees, shares.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `trader` without reporting or sleeping until `days` market days went by or it stops.
+/// Days are the markets' own clock, only the waits the strategy does move it.
+/// A strategy that goes `MAX_TICKS_PER_DAY` ticks without a new day ends the run early.
+pub fn backtest(trader: &mut Trader, strategy: &str, days: u32) -> BacktestReport {
+    let start = trader.core().get_budget();
+    //a resumed ZSE would otherwise count its earlier runs into the first day
+    trader.core().markets.take_zse_stats();
+    let mut curve = Vec::new();
+    let mut idle = 0;
+    while trader.core().day() < days && idle < MAX_TICKS_PER_DAY {
+        let day = trader.core().day();
+        let running = trader.step();
+        let core = trader.core();
+        if core.day() > day || !running {
+            let holdings = core.holdings_eur();
+            curve.push(EquityPoint {
+                day: core.day(),
+                total: holdings.iter().sum(),
+                holdings,
+                zse: core.markets.take_zse_stats(),
+                treasury: core.markets.zse_treasury(),
+            });
+            idle = 0;
+        } else {
+            idle += 1;
+        }
+        if !running {
+            break;
+        }
+    }
+
+    let core = trader.core();
+    let bus = core.markets.bus().borrow();
+    BacktestReport {
+        strategy: strategy.to_string(),
+        days: core.day(),
+        start,
+        quantities: (0..4).map(|i| core.get_qty(i)).collect(),
+        curve,
+        counts: core.counts(),
+        deliveries: bus.delivered(),
+        echoes: bus.echoes(),
+        pending: bus.pending(),
+    }
+}
+
+/// `zse` for runs nobody watches: nothing goes to log_ZSE.txt, which a visual run may be writing.
+pub fn headless(zse: &ZseConfig) -> ZseConfig {
+    ZseConfig { log_format: LogFormat::Off, ..zse.clone() }
+}
+
+/// Where the ZSE of a backtest starts from and where it ends up.
+pub struct SnapshotPaths {
+    /// market saved by an earlier run, instead of a fresh one
+    pub resume: Option<String>,
+    /// saves the market as the run left it
+    pub save: Option<String>,
+}
+
+pub fn run(strategy: &str, days: u32, budget: f32, equity: &str, seed: Option<u64>, zse: &ZseConfig, snapshots: &SnapshotPaths) {
+    let boxed = match strategy_by_name(strategy) {
+        Some(boxed) => boxed,
+        None => {
+            println!("Unknown strategy {}, known ones are {}", strategy, STRATEGIES.join(", "));
+            return;
+        }
+    };
+    let mut rng = StdRng::seed_from_u64(crate::run_seed(seed));
+    let values = MarketRegistry::random_quantities(&mut rng);
+    let split = crate::split_budget(budget, &mut rng);
+    let mut rng = StdRng::seed_from_u64(rng.gen());
+    let mut markets = MarketRegistry::new_with_quantities(&values, &headless(zse), &mut rng);
+    if let Some(path) = &snapshots.resume {
+        if let Err(e) = markets.resume_zse(path) {
+            println!("Cannot resume from {}: {}", path, e);
+            return;
+        }
+    }
+    let mut trader = Trader::new(boxed, 1, 0, split, markets, rng);
+
+    let report = backtest(&mut trader, strategy, days);
+    report.print();
+    match report.write_equity(equity) {
+        Ok(_) => println!("\nEquity curve written to {}", equity),
+        Err(e) => println!("\nCannot write {}: {}", equity, e),
+    }
+    if let Some(path) = &snapshots.save {
+        match trader.core().markets.save_zse(path) {
+            Some(Ok(_)) => println!("ZSE saved to {}", path),
+            Some(Err(e)) => println!("Cannot save ZSE to {}: {}", path, e),
+            None => {}
+        }
+    }
+}
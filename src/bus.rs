@@ -0,0 +1,334 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::{Rc, Weak};
+
+use unitn_market_2022::event::event::{Event, EventKind};
+use unitn_market_2022::event::notifiable::Notifiable;
+use unitn_market_2022::good::good_kind::GoodKind;
+use unitn_market_2022::market::Market;
+
+/// One event on its way through the bus.
+#[derive(Clone)]
+pub struct Envelope {
+    /// market that published it, or `EventBus::ORIGIN` for events the bus makes itself
+    pub origin: String,
+    pub event: Event,
+}
+
+/// Delivers every event to every connected market but the one it came from, exactly once.
+/// Markets don't subscribe to each other: each one gets a `BusPort` as its only subscriber, so anything a market
+/// re-broadcasts from its `on_event` comes back to the bus as an echo of the event being delivered, and is dropped there.
+/// A market that is busy when its turn comes (it is the one trading, or it is further up the stack) gets the event
+/// later, in publish order, as soon as a delivery finds it free again.
+pub struct EventBus {
+    markets: Vec<(String, Rc<RefCell<dyn Market>>)>,
+    //event being delivered right now
+    delivering: Option<Envelope>,
+    //deliveries not made yet, index into markets, oldest first
+    pending: VecDeque<(usize, Envelope)>,
+    delivered: u64,
+    //re-broadcasts dropped
+    echoes: u64,
+    //`Wait` events the bus made, the day clock every market on it shares
+    days: u32,
+}
+
+/// What a market sees as its subscriber, publishes into the bus under the market's name.
+pub struct BusPort {
+    bus: Weak<RefCell<EventBus>>,
+    origin: String,
+}
+
+impl EventBus {
+    pub const ORIGIN: &'static str = "bus";
+
+    pub fn new() -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(EventBus {
+            markets: Vec::new(),
+            delivering: None,
+            pending: VecDeque::new(),
+            delivered: 0,
+            echoes: 0,
+            days: 0,
+        }))
+    }
+
+    /// Puts `market` on the bus under `name`, names have to be unique.
+    pub fn connect(bus: &Rc<RefCell<Self>>, name: &str, market: Rc<RefCell<dyn Market>>) {
+        let port = BusPort {
+            bus: Rc::downgrade(bus),
+            origin: name.to_string(),
+        };
+        market.borrow_mut().add_subscriber(Box::new(port));
+        bus.borrow_mut().markets.push((name.to_string(), market));
+    }
+
+    /// Puts `market` on the bus in place of the one connected as `name`, which hears nothing more.
+    pub fn replace(bus: &Rc<RefCell<Self>>, name: &str, market: Rc<RefCell<dyn Market>>) {
+        let port = BusPort {
+            bus: Rc::downgrade(bus),
+            origin: name.to_string(),
+        };
+        market.borrow_mut().add_subscriber(Box::new(port));
+        if let Some(entry) = bus.borrow_mut().markets.iter_mut().find(|(n, _)| n == name) {
+            entry.1 = market;
+        }
+    }
+
+    /// Hands `event` to every market but `origin`, after whatever busy markets still owe from earlier events.
+    pub fn publish(bus: &Rc<RefCell<Self>>, origin: &str, event: Event) {
+        {
+            let mut guard = bus.borrow_mut();
+            let b = &mut *guard;
+            if b.delivering.is_some() {
+                b.echoes += 1;
+                return;
+            }
+            let envelope = Envelope {
+                origin: origin.to_string(),
+                event,
+            };
+            for (i, (name, _)) in b.markets.iter().enumerate() {
+                if *name != envelope.origin {
+                    b.pending.push_back((i, envelope.clone()));
+                }
+            }
+        }
+        Self::flush(bus);
+    }
+
+    /// Makes the deliveries busy markets couldn't take, `publish` does it on every event.
+    /// A market still busy keeps its deliveries, and gets nothing newer before them.
+    pub fn flush(bus: &Rc<RefCell<Self>>) {
+        let queued: Vec<(usize, Envelope)> = {
+            let mut b = bus.borrow_mut();
+            if b.delivering.is_some() {
+                return;
+            }
+            b.pending.drain(..).collect()
+        };
+
+        let mut busy = Vec::new();
+        let mut left = VecDeque::new();
+        for (i, envelope) in queued {
+            let market = bus.borrow().markets[i].1.clone();
+            //the bus is not borrowed while markets run, their echoes need it
+            let mut market = match market.try_borrow_mut() {
+                Ok(market) if !busy.contains(&i) => market,
+                _ => {
+                    busy.push(i);
+                    left.push_back((i, envelope));
+                    continue;
+                }
+            };
+            let event = envelope.event.clone();
+            bus.borrow_mut().delivering = Some(envelope);
+            market.on_event(event);
+            drop(market);
+
+            let mut b = bus.borrow_mut();
+            b.delivering = None;
+            b.delivered += 1;
+        }
+        bus.borrow_mut().pending = left;
+    }
+
+    /// One market day for every market on the bus.
+    pub fn wait_one_day(bus: &Rc<RefCell<Self>>) {
+        let event = Event {
+            kind: EventKind::Wait,
+            good_kind: GoodKind::EUR,
+            quantity: 0.0,
+            price: 0.0,
+        };
+        bus.borrow_mut().days += 1;
+        Self::publish(bus, Self::ORIGIN, event);
+    }
+
+    /// Market days gone by since the bus was made.
+    pub fn days(&self) -> u32 {
+        self.days
+    }
+
+    /// Deliveries waiting for a busy market.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Deliveries to single markets so far.
+    pub fn delivered(&self) -> u64 {
+        self.delivered
+    }
+
+    /// Re-broadcasts dropped so far.
+    pub fn echoes(&self) -> u64 {
+        self.echoes
+    }
+}
+
+impl Notifiable for BusPort {
+    //the bus is the only subscriber a market needs
+    fn add_subscriber(&mut self, _subscriber: Box<dyn Notifiable>) {}
+
+    fn on_event(&mut self, event: Event) {
+        if let Some(bus) = self.bus.upgrade() {
+            EventBus::publish(&bus, &self.origin, event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use unitn_market_2022::event::event::{Event, EventKind};
+    use unitn_market_2022::event::notifiable::Notifiable;
+    use unitn_market_2022::good::good::Good;
+    use unitn_market_2022::good::good_kind::GoodKind;
+    use unitn_market_2022::market::good_label::GoodLabel;
+    use unitn_market_2022::market::{BuyError, LockBuyError, LockSellError, Market, MarketGetterError, SellError};
+
+    use super::EventBus;
+    use crate::market::{ZseConfig, ZSE};
+
+    //counts what it is told and tells it right back, like the markets that subscribe to each other
+    struct Echo {
+        seen: Vec<EventKind>,
+        subscribers: Vec<Box<dyn Notifiable>>,
+    }
+
+    impl Echo {
+        fn new() -> Rc<RefCell<Echo>> {
+            Rc::new(RefCell::new(Echo { seen: Vec::new(), subscribers: Vec::new() }))
+        }
+
+        //a trade of its own
+        fn trade(&mut self) {
+            for s in &mut self.subscribers {
+                s.on_event(event(EventKind::Bought));
+            }
+        }
+    }
+
+    impl Notifiable for Echo {
+        fn add_subscriber(&mut self, subscriber: Box<dyn Notifiable>) {
+            self.subscribers.push(subscriber);
+        }
+
+        fn on_event(&mut self, event: Event) {
+            self.seen.push(event.kind.clone());
+            for s in &mut self.subscribers {
+                s.on_event(event.clone());
+            }
+        }
+    }
+
+    impl Market for Echo {
+        fn new_random() -> Rc<RefCell<dyn Market>> where Self: Sized {
+            Echo::new()
+        }
+
+        fn new_with_quantities(_eur: f32, _yen: f32, _usd: f32, _yuan: f32) -> Rc<RefCell<dyn Market>> where Self: Sized {
+            Echo::new()
+        }
+
+        fn new_file(_path: &str) -> Rc<RefCell<dyn Market>> where Self: Sized {
+            Echo::new()
+        }
+
+        fn get_name(&self) -> &'static str {
+            "Echo"
+        }
+
+        fn get_budget(&self) -> f32 {
+            0.0
+        }
+
+        fn get_buy_price(&self, _kind: GoodKind, _quantity: f32) -> Result<f32, MarketGetterError> {
+            Err(MarketGetterError::NonPositiveQuantityAsked)
+        }
+
+        fn get_sell_price(&self, _kind: GoodKind, _quantity: f32) -> Result<f32, MarketGetterError> {
+            Err(MarketGetterError::NonPositiveQuantityAsked)
+        }
+
+        fn get_goods(&self) -> Vec<GoodLabel> {
+            Vec::new()
+        }
+
+        fn lock_buy(&mut self, _kind_to_buy: GoodKind, _quantity_to_buy: f32, _bid: f32, _trader_name: String) -> Result<String, LockBuyError> {
+            Err(LockBuyError::MaxAllowedLocksReached)
+        }
+
+        fn buy(&mut self, token: String, _cash: &mut Good) -> Result<Good, BuyError> {
+            Err(BuyError::UnrecognizedToken { unrecognized_token: token })
+        }
+
+        fn lock_sell(&mut self, _kind_to_sell: GoodKind, _quantity_to_sell: f32, _offer: f32, _trader_name: String) -> Result<String, LockSellError> {
+            Err(LockSellError::MaxAllowedLocksReached)
+        }
+
+        fn sell(&mut self, token: String, _good: &mut Good) -> Result<Good, SellError> {
+            Err(SellError::UnrecognizedToken { unrecognized_token: token })
+        }
+    }
+
+    fn event(kind: EventKind) -> Event {
+        Event { kind, good_kind: GoodKind::EUR, quantity: 0.0, price: 0.0 }
+    }
+
+    //ZSE and two echoing markets, every one of them re-broadcasting into the bus
+    fn setup() -> (Rc<RefCell<EventBus>>, Rc<RefCell<ZSE>>, Rc<RefCell<Echo>>, Rc<RefCell<Echo>>) {
+        let path = std::env::temp_dir().join("zse_bus_test.txt");
+        let zse = ZSE::with_config(10000.0, 10000.0, 10000.0, 10000.0, ZseConfig::builder().log_path(path.to_str().unwrap()).build(), 0);
+        let (a, b) = (Echo::new(), Echo::new());
+        let bus = EventBus::new();
+        EventBus::connect(&bus, "ZSE", zse.clone());
+        EventBus::connect(&bus, "A", a.clone());
+        EventBus::connect(&bus, "B", b.clone());
+        (bus, zse, a, b)
+    }
+
+    #[test]
+    fn every_market_gets_an_event_once() {
+        let (bus, zse, a, b) = setup();
+        let zse_events = zse.borrow().stats().events;
+
+        EventBus::wait_one_day(&bus);
+        a.borrow_mut().trade();
+        let bid = zse.borrow().get_buy_price(GoodKind::USD, 1.0).unwrap();
+        zse.borrow_mut().lock_buy(GoodKind::USD, 1.0, bid, "tester".to_string()).unwrap();
+
+        //the wait and A's trade reach ZSE, its own lock is handled once
+        assert_eq!(zse.borrow().stats().events, zse_events + 3);
+        assert_eq!(a.borrow().seen.len(), 2);
+        assert_eq!(b.borrow().seen.len(), 3);
+        assert_eq!(bus.borrow().delivered(), 3 + 2 + 2);
+        assert_eq!(bus.borrow().pending(), 0);
+        //A and B echo back every event they are given
+        assert_eq!(bus.borrow().echoes(), 5);
+    }
+
+    #[test]
+    fn busy_market_gets_its_events_later_in_order() {
+        let (bus, _zse, a, b) = setup();
+
+        {
+            let _busy = a.borrow_mut();
+            b.borrow_mut().trade();
+            EventBus::wait_one_day(&bus);
+            assert_eq!(bus.borrow().pending(), 2);
+        }
+        assert!(a.borrow().seen.is_empty());
+
+        EventBus::flush(&bus);
+        assert_eq!(bus.borrow().pending(), 0);
+        let a = a.borrow();
+        let seen = &a.seen;
+        assert_eq!(seen.len(), 2);
+        assert!(matches!(seen[0], EventKind::Bought));
+        assert!(matches!(seen[1], EventKind::Wait));
+        assert_eq!(b.borrow().seen.len(), 1);
+    }
+}
@@ -31,11 +31,9 @@ impl Dataset {
         }
     }
 
-    pub fn append_points(&mut self, message: Vec<&str>, count: f64) {
-        let eur = message[0].parse::<f64>().unwrap();
-        let usd = message[1].parse::<f64>().unwrap();
-        let yen = message[2].parse::<f64>().unwrap();
-        let yuan = message[3].parse::<f64>().unwrap();
+    //values are EUR USD YEN YUAN, valued in EUR
+    pub fn append_points(&mut self, values: [f32; 4], count: f64) {
+        let [eur, usd, yen, yuan] = values.map(|v| v as f64);
         self.capital.push(PlotPoint {
             x: count,
             y: eur + usd + yen + yuan,
@@ -3,18 +3,25 @@ use std::time::Duration;
 use std::thread;
 
 
-use bfb::bfb_market::Bfb;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use eframe::{egui, run_native};
-use rand::{thread_rng, Rng};
-use rcnz_market::rcnz::RCNZ;
-use unitn_market_2022::market::good_label::GoodLabel;
-use unitn_market_2022::market::Market;
-use BVC::BVCMarket;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
+use market::{PriceCurve, ZseConfig};
+use trader_core::TraderEvent;
+
+mod backtest;
+mod bus;
 mod coolvisualizer;
+mod market;
+mod oracle;
+mod registry;
+mod replay;
+mod tournament;
 mod trader;
 mod trader_balordo;
+mod trader_core;
 
 const TX_DELAY_MS: u64 = 200;
 const STARTING_BUDGET: f32 = 40000.0;
@@ -30,39 +37,135 @@ pub struct Args {
 
     #[arg(short, long, default_value = "from ZSE")]
     name: String,
+
+    /// strategies plotted in the left and right panel
+    #[arg(short, long, value_delimiter = ',', default_value = "dropship,3M")]
+    strategies: Vec<String>,
+
+    /// makes the run reproducible: goods, budget split, strategies and ZSE tokens all come from it
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// TOML file with the limits of our own market, see `ZseConfig::from_toml_str`
+    #[arg(long)]
+    zse_config: Option<String>,
+
+    /// price impact of ZSE quotes: linear, constant_product or tiered.
+    /// Overrides the config file, tiered when neither sets it
+    #[arg(long)]
+    curve: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Rebuild the inventory timeline and trading stats from a ZSE text log
+    Replay {
+        #[arg(default_value = "log_ZSE.txt")]
+        path: String,
+
+        /// also write the inventory timeline as CSV
+        #[arg(short, long)]
+        timeline: Option<String>,
+    },
+    /// Run one strategy without the GUI and print its PnL
+    Backtest {
+        #[arg(default_value = "dropship")]
+        strategy: String,
+
+        /// market days to run for
+        #[arg(long, default_value_t = 100)]
+        days: u32,
+
+        /// where the equity curve goes, as CSV
+        #[arg(short, long, default_value = "equity.csv")]
+        equity: String,
+
+        /// start ZSE where a previous --snapshot left it instead of from fresh goods
+        #[arg(long)]
+        resume: Option<String>,
+
+        /// save ZSE as the run leaves it, for a later --resume
+        #[arg(long)]
+        snapshot: Option<String>,
+    },
+    /// Run every strategy from the same starting point for many seeds and rank them
+    Tournament {
+        /// runs this many seeds from --seed on (0 without it), each one a new starting point
+        #[arg(long, default_value_t = 20)]
+        seeds: u64,
+
+        /// market days each run lasts
+        #[arg(long, default_value_t = 100)]
+        days: u32,
+
+        /// one row per run
+        #[arg(short, long, default_value = "tournament.csv")]
+        csv: String,
+
+        /// worker threads, one per core when missing
+        #[arg(short, long)]
+        threads: Option<usize>,
+    },
 }
 
 fn main() {
     let args = Args::parse();
+    let zse = match zse_config(&args) {
+        Ok(zse) => zse,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    match args.command {
+        Some(Command::Replay { path, timeline }) => {
+            if let Err(e) = replay::run(&path, timeline) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Backtest { strategy, days, equity, resume, snapshot }) => {
+            let snapshots = backtest::SnapshotPaths { resume, save: snapshot };
+            backtest::run(&strategy, days, args.budget, &equity, args.seed, &zse, &snapshots);
+            return;
+        }
+        Some(Command::Tournament { seeds, days, csv, threads }) => {
+            tournament::run(seeds, days, args.budget, &csv, threads, args.seed, &zse);
+            return;
+        }
+        None => {}
+    }
+    let mut rng = StdRng::seed_from_u64(run_seed(args.seed));
+
     //Market init
-    let bfb = Bfb::new_random();
-    let rcnz = RCNZ::new_random();
-    let bvc = BVCMarket::new_random();
-    let values_bfb = bfb.borrow().get_goods();
-    let values_rcnz = rcnz.borrow().get_goods();
-    let values_bvc = bvc.borrow().get_goods();
+    let values = registry::MarketRegistry::random_quantities(&mut rng);
 
     //trader init
-    let mut remaining = args.budget;
-    let mut tmp = vec![0.0; 4];
-    for i in 0..3 {
-        tmp[i] = thread_rng().gen_range(0.0..remaining);
-        remaining -= tmp[i];
+    let tmp = split_budget(args.budget, &mut rng);
+
+    if args.strategies.len() != 2 {
+        eprintln!("expected two strategies, got {}", args.strategies.len());
+        return;
     }
-    tmp[3] = remaining;
-
-    let mut trader1 = trader_balordo::ZSE_Trader::new_with_quantities(
-        tmp.clone(),
-        parse(&values_rcnz),
-        parse(&values_bfb),
-        parse(&values_bvc),
-    );
-    let mut trader2 = trader::ZSE_Trader::new_with_quantities(
-        tmp.clone(),
-        parse(&values_rcnz),
-        parse(&values_bfb),
-        parse(&values_bvc),
-    );
+    let mut traders = Vec::new();
+    for (i, name) in args.strategies.iter().enumerate() {
+        let strategy = match trader_core::strategy_by_name(name) {
+            Some(strategy) => strategy,
+            None => {
+                eprintln!("unknown strategy {}, known ones are {}", name, trader_core::STRATEGIES.join(", "));
+                return;
+            }
+        };
+        //the id picks the panel: 1 left, 2 right
+        let id = i as u32 + 1;
+        traders.push(trader_core::Trader::new_with_quantities(strategy, id, args.delay, tmp.clone(), values.clone(), &zse, rng.gen()));
+    }
+    let mut trader2 = traders.pop().unwrap();
+    let mut trader1 = traders.pop().unwrap();
 
     //visualizer init
     let visualizer = coolvisualizer::Visualizer::new();
@@ -83,21 +186,24 @@ fn main() {
     });
     thread::spawn(move || {
         let mut count = 0;
-        for str in rx {
+        for event in rx {
+            //only portfolio updates are plotted
+            let (trader, values) = match event {
+                TraderEvent::PortfolioUpdate { trader, values, .. } => (trader, values),
+                _ => continue,
+            };
             //append data to the vector to make it visible in the plot
-            let mut data = str.split_whitespace().collect::<Vec<&str>>();
-            let id = data.remove(0);
-            if id == "1" {
+            if trader == 1 {
                 dataset_dropship
                     .lock()
                     .unwrap()
-                    .append_points(data.clone(), count as f64);
+                    .append_points(values, count as f64);
             }
-            if id == "2" {
+            if trader == 2 {
                 dataset_3m
                     .lock()
                     .unwrap()
-                    .append_points(data.clone(), count as f64);
+                    .append_points(values, count as f64);
             }
 
             //print_vector(&dataset.lock().unwrap().get_points());
@@ -123,10 +229,35 @@ fn set_native_options() -> eframe::NativeOptions {
     }
 }
 
-fn parse(v: &Vec<GoodLabel>) -> Vec<f32> {
+//our own market in every run, whatever the subcommand
+fn zse_config(args: &Args) -> Result<ZseConfig, String> {
+    let mut zse = match &args.zse_config {
+        Some(path) => ZseConfig::load(path).map_err(|e| format!("{}: {}", path, e))?,
+        None => ZseConfig::builder().curve(PriceCurve::default_tiers()).build(),
+    };
+    if let Some(name) = &args.curve {
+        zse.curve = PriceCurve::from_name(name).ok_or_else(|| format!("unknown curve {}, known ones are linear, constant_product, tiered", name))?;
+    }
+    Ok(zse)
+}
+
+//a missing seed is drawn once and printed, so any run can be repeated with --seed
+fn run_seed(seed: Option<u64>) -> u64 {
+    seed.unwrap_or_else(|| {
+        let seed = rand::random();
+        println!("Seed: {}", seed);
+        seed
+    })
+}
+
+//random split of the budget into EUR USD YEN YUAN, valued in EUR
+fn split_budget(budget: f32, rng: &mut impl Rng) -> Vec<f32> {
+    let mut remaining = budget;
     let mut res = vec![0.0; 4];
-    for i in 0..4 {
-        res[i] = v[i].quantity;
+    for i in 0..3 {
+        res[i] = rng.gen_range(0.0..remaining);
+        remaining -= res[i];
     }
+    res[3] = remaining;
     res
 }
@@ -1,7 +1,10 @@
-use std::collections::HashMap;
+use std::fmt::Debug;
 use std::rc::Rc;
 use std::cell::RefCell;
 
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
 use unitn_market_2022::good::good::Good;
 use unitn_market_2022::good::good_kind::GoodKind;
 use unitn_market_2022::good::consts::{DEFAULT_EUR_YUAN_EXCHANGE_RATE, DEFAULT_EUR_YEN_EXCHANGE_RATE, DEFAULT_EUR_USD_EXCHANGE_RATE, STARTING_CAPITAL};
@@ -10,6 +13,45 @@ use unitn_market_2022::market::good_label::GoodLabel;
 use unitn_market_2022::event::event::Event;
 use unitn_market_2022::event::notifiable::Notifiable;
 
+use crate::oracle::{RateOracle, RateSource};
+
+mod breaker;
+mod cancel;
+mod config;
+mod curve;
+mod expiry;
+mod fees;
+mod file;
+mod log;
+mod partial;
+mod pricing;
+mod snapshot;
+mod stats;
+mod tokens;
+mod traders;
+mod treasury;
+
+pub use breaker::{BreakerEvent, CircuitBreakers, HaltReason};
+pub use cancel::{CancelLock, CancelLockError};
+pub use config::{ConfigError, ZseConfig, ZseConfigBuilder};
+pub use curve::PriceCurve;
+pub use expiry::ExpiryClock;
+pub use fees::FeeSchedule;
+pub use file::ZseFileError;
+pub use log::{variant_name, JsonLinesSink, LogFormat, LogRecord, LogSink, NullSink, Op, Outcome, TextSink};
+pub use partial::{Fill, PartialFill};
+pub use pricing::{DumpingStrategy, FixedSpreadStrategy, InventorySkewStrategy, PricingKind, PricingStrategy};
+pub use snapshot::SnapshotError;
+pub use stats::ZseStats;
+pub use traders::{TraderLimits, ZseTraderError};
+pub use treasury::{Rebalance, TreasuryTargets};
+
+use breaker::BreakerState;
+use stats::StatsBook;
+use tokens::TokenTable;
+use traders::TraderBook;
+use treasury::RebalanceHistory;
+
 pub struct ZSE {
     goods: [Good; 4],
     prices_sell: [f32; 4],
@@ -17,14 +59,25 @@ pub struct ZSE {
     lock_buy: [Lock; 4],
     lock_sell: [Lock; 4],
     locked_qty: [f32; 4],
-    token: HashMap<String, bool>,
+    token: TokenTable,
+    traders: TraderBook,
+    //EUR collected as fees, kept out of goods so it is never traded
+    fee_account: f32,
+    rates: Box<dyn RateOracle>,
     markets: Vec<Box<dyn Notifiable>>,
-    external: bool,
     conversion_timer: [[i32; 4]; 4],
+    rebalances: RebalanceHistory,
+    breaker: BreakerState,
+    stats: StatsBook,
+    pricing: Box<dyn PricingStrategy>,
+    logger: Box<dyn LogSink>,
+    //salts tokens, same seed same tokens run after run
+    rng: StdRng,
+    config: ZseConfig,
 }
 
 struct Lock {
-    lock: [Contract; MAXLOCK],
+    lock: Vec<Contract>,
     last: i32,
 }
 
@@ -32,204 +85,92 @@ struct Contract {
     token: String,
     quantity: f32,
     price: f32,
+    //EUR going to the fee account when the contract executes, on top of price for sell locks
+    fee: f32,
     lock_counter: i32,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum Mode {
     Buy,
     Sell,
 }
 
-const MAXLOCK: usize = 3;
-const MAXTIME: i32 = 15;
-const PATH_LOG: &str = "log_ZSE.txt";
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum TokenState {
+    Active,
+    Executed,
+    Expired,
+    Cancelled,
+}
 
 impl Notifiable for ZSE {
     fn add_subscriber(&mut self, subscriber: Box<dyn Notifiable>) {
         self.markets.push(subscriber);
     }
 
+    //everything reaching us this way comes from another market, our own events go through `publish`
     fn on_event(&mut self, event: Event) {
+        self.handle_event(event, false);
+    }
+}
+
+impl ZSE {
+    //an event of ours: handled like any other, then told to our subscribers
+    fn publish(&mut self, event: Event) {
+        self.handle_event(event, true);
+    }
+
+    fn handle_event(&mut self, event: Event, own: bool) {
         use unitn_market_2022::event::event::EventKind;
 
-        self.increment_lock_counter_and_reset();
-        for m in &mut self.markets {
-            if !self.external {
+        self.token.tick();
+        if let EventKind::Wait = event.kind {
+            self.traders.new_day();
+            self.breaker_new_day();
+            self.logger.flush();
+        }
+        self.age_locks(&event);
+        if own {
+            for m in &mut self.markets {
                 m.on_event(event.clone());
             }
         }
 
-        let unit_price = event.price / event.quantity;
-        let index = self.get_index_by_goodkind(&event.good_kind);
-        let exchange = match event.good_kind {
-            GoodKind::EUR => 1.0,
-            GoodKind::USD => DEFAULT_EUR_USD_EXCHANGE_RATE,
-            GoodKind::YEN => DEFAULT_EUR_YEN_EXCHANGE_RATE,
-            GoodKind::YUAN => DEFAULT_EUR_YUAN_EXCHANGE_RATE,
-        };
+        //quotes stay frozen while trading is halted
+        if self.halted().is_none() {
+            let (prices_buy, prices_sell) = self.pricing.reprice(&event, !own, &self.goods, &self.prices_buy, &self.prices_sell);
+            self.prices_buy = prices_buy;
+            self.prices_sell = prices_sell;
+        }
 
-        // dumping strategy
-        match event.kind {
-            EventKind::Bought => {
-                if !self.external {
-                    let diff = exchange - self.prices_buy[index];
-                    self.prices_buy[index] += diff * 0.8;
-                    self.internal_conversion();
-                }
-                if self.external && unit_price < self.prices_buy[index] {
-                    self.prices_buy[index] = unit_price - (unit_price * 0.015);
+        if own {
+            match event.kind {
+                EventKind::Bought | EventKind::Sold => {
+                    self.rebalance();
                 }
-            },
-            EventKind::Sold => {
-                if !self.external {
-                    let diff = self.prices_sell[index] - exchange;
-                    self.prices_sell[index] -= diff * 0.8;
-                    self.internal_conversion();
-                }
-                if self.external && unit_price > self.prices_sell[index] {
-                    self.prices_sell[index] = unit_price + (unit_price * 0.015);
-                }
-            },
-            EventKind::LockedBuy => {
-                if self.external && unit_price > self.prices_sell[index] {
-                    self.prices_sell[index] = unit_price + (unit_price * 0.01);
-                }
-            },
-            EventKind::LockedSell => {
-                if self.external && unit_price < self.prices_buy[index] {
-                    self.prices_buy[index] = unit_price - (unit_price * 0.01);
-                }
-            },
-            _ => {}
-        };
-        self.external = true;
+                _ => {}
+            }
+        }
+        self.check_breakers();
+        self.sample_stats();
         self.decrement_conversion_timer();
     }
 }
 
 impl Market for ZSE {
+    //the trait can't take a seed, so every market made through it gets its own split and token salts,
+    //reproducible runs go through `random_with_config`/`with_config`
     fn new_random() -> Rc<RefCell<dyn Market>> where Self: Sized {
-        use rand::Rng;
-
-        let mut remaining = STARTING_CAPITAL as i32;
-        let mut tmp = vec![0.0; 4];
-        let mut random_num;
-
-        for i in 0..3 {
-            random_num = rand::thread_rng().gen_range(0..remaining);
-            tmp[i] = random_num as f32;
-            remaining -= random_num;
-        }
-        tmp[3] = remaining as f32;
-
-        let market = ZSE {
-            goods: [
-                Good::new(GoodKind::EUR, tmp[0]),
-                Good::new(GoodKind::USD, tmp[1] * DEFAULT_EUR_USD_EXCHANGE_RATE),
-                Good::new(GoodKind::YEN, tmp[2] * DEFAULT_EUR_YEN_EXCHANGE_RATE),
-                Good::new(GoodKind::YUAN, tmp[3] * DEFAULT_EUR_YUAN_EXCHANGE_RATE),
-            ],
-            prices_sell: [
-                1.0,
-                DEFAULT_EUR_USD_EXCHANGE_RATE,
-                DEFAULT_EUR_YEN_EXCHANGE_RATE,
-                DEFAULT_EUR_YUAN_EXCHANGE_RATE,
-            ],
-            prices_buy: [
-                1.0,
-                DEFAULT_EUR_USD_EXCHANGE_RATE,
-                DEFAULT_EUR_YEN_EXCHANGE_RATE,
-                DEFAULT_EUR_YUAN_EXCHANGE_RATE,
-            ],
-            lock_buy: [Lock::new(), Lock::new(), Lock::new(), Lock::new()],
-            lock_sell: [Lock::new(), Lock::new(), Lock::new(), Lock::new()],
-            locked_qty: [0.0; 4],
-            token: HashMap::new(),
-            markets: Vec::new(),
-            external: true,
-            conversion_timer: [[0; 4]; 4],
-        };
-
-        init_file();
-        let logcode = format!(
-            "MARKET INITIALIZATION \n EUR: {:+e} \n USD: {:+e} \n YEN: {:+e} \n YUAN: {:+e} \n END MARKET INITIALIZATION",
-            tmp[0], tmp[1], tmp[2], tmp[3]);
-        print_metadata(logcode);
-
-        Rc::new(RefCell::new(market))
+        ZSE::random_with_config(ZseConfig::default(), rand::random())
     }
 
     fn new_with_quantities(eur: f32, yen: f32, usd: f32, yuan: f32) -> Rc<RefCell<dyn Market>> where Self: Sized {
-        let market = ZSE {
-            goods: [
-                Good::new(GoodKind::EUR, eur),
-                Good::new(GoodKind::USD, usd),
-                Good::new(GoodKind::YEN, yen),
-                Good::new(GoodKind::YUAN, yuan),
-            ],
-            prices_sell: [
-                1.0,
-                DEFAULT_EUR_USD_EXCHANGE_RATE,
-                DEFAULT_EUR_YEN_EXCHANGE_RATE,
-                DEFAULT_EUR_YUAN_EXCHANGE_RATE,
-            ],
-            prices_buy: [
-                1.0,
-                DEFAULT_EUR_USD_EXCHANGE_RATE,
-                DEFAULT_EUR_YEN_EXCHANGE_RATE,
-                DEFAULT_EUR_YUAN_EXCHANGE_RATE,
-            ],
-            lock_buy: [Lock::new(), Lock::new(), Lock::new(), Lock::new()],
-            lock_sell: [Lock::new(), Lock::new(), Lock::new(), Lock::new()],
-            locked_qty: [0.0; 4],
-            token: HashMap::new(),
-            markets: Vec::new(),
-            external: true,
-            conversion_timer: [[0; 4]; 4],
-        };
-
-        init_file();
-        let logcode = format!(
-            "MARKET INITIALIZATION \n EUR: {:+e} \n USD: {:+e} \n YEN: {:+e} \n YUAN: {:+e} \n END MARKET INITIALIZATION",
-            eur, usd, yen, yuan
-        );
-        print_metadata(logcode);
-
-        Rc::new(RefCell::new(market))
+        ZSE::with_config(eur, yen, usd, yuan, ZseConfig::default(), rand::random())
     }
 
     fn new_file(path: &str) -> Rc<RefCell<dyn Market>> where Self: Sized {
-        use std::fs::File;
-        use std::io::BufRead;
-
-        let file = File::open(path);
-        if file.is_err() {
-            return Self::new_random();
-        }
-
-        let mut eur = 0.0;
-        let mut usd = 0.0;
-        let mut yen = 0.0;
-        let mut yuan = 0.0;
-
-        let reader = std::io::BufReader::new(file.unwrap());
-        for line in reader.lines() {
-            let line = line.unwrap();
-            let mut split = line.split(" ");
-            let good = split.next().unwrap();
-            let quantity = split.next().unwrap();
-            let quantity = quantity.parse::<f32>().unwrap();
-            match good {
-                "EUR" => eur = quantity,
-                "USD" => usd = quantity,
-                "YEN" => yen = quantity,
-                "YUAN" => yuan = quantity,
-                _ => {}
-            }
-        }
-
-        Self::new_with_quantities(eur, yen, usd, yuan)
+        ZSE::file_or_random(path, ZseConfig::default(), rand::random())
     }
 
     fn get_name(&self) -> &'static str {
@@ -237,7 +178,7 @@ impl Market for ZSE {
     }
 
     fn get_budget(&self) -> f32 {
-        self.goods.iter().map(|good| Self::convert_to_eur(good)).sum()
+        self.goods.iter().map(|good| self.rates.to_eur(good)).sum()
     }
 
     fn get_buy_price(&self, kind: GoodKind, quantity: f32) -> Result<f32, MarketGetterError> {
@@ -250,8 +191,10 @@ impl Market for ZSE {
             return Err(MarketGetterError::InsufficientGoodQuantityAvailable { requested_good_kind: kind, requested_good_quantity: quantity, available_good_quantity: internal_quantity });
         }
 
-        let price = self.prices_buy[self.get_index_by_goodkind(&kind)];
-        Ok(price * quantity)
+        match self.buy_cost(self.get_index_by_goodkind(&kind), quantity) {
+            Some(cost) => Ok(cost + self.fee_on(cost, None)),
+            None => Err(MarketGetterError::InsufficientGoodQuantityAvailable { requested_good_kind: kind, requested_good_quantity: quantity, available_good_quantity: internal_quantity }),
+        }
     }
 
     fn get_sell_price(&self, kind: GoodKind, quantity: f32) -> Result<f32, MarketGetterError> {
@@ -259,8 +202,8 @@ impl Market for ZSE {
             return Err(MarketGetterError::NonPositiveQuantityAsked);
         }
 
-        let price = self.prices_sell[self.get_index_by_goodkind(&kind)];
-        Ok(price * quantity)
+        let payout = self.sell_payout(self.get_index_by_goodkind(&kind), quantity);
+        Ok((payout - self.fee_on(payout, None)).max(0.0))
     }
 
     fn get_goods(&self) -> Vec<GoodLabel> {
@@ -284,44 +227,59 @@ impl Market for ZSE {
     fn lock_buy(&mut self, kind_to_buy: GoodKind, quantity_to_buy: f32, bid: f32, trader_name: String) -> Result<String, LockBuyError> {
         use unitn_market_2022::event::event::EventKind;
 
-        self.external = false;
-        self.on_event(Event { kind: EventKind::LockedBuy, quantity: quantity_to_buy, price: bid, good_kind: kind_to_buy });
+        self.publish(Event { kind: EventKind::LockedBuy, quantity: quantity_to_buy, price: bid, good_kind: kind_to_buy });
 
-        let logcode = format!("LOCK_BUY-{}-KIND_TO_BUY:{}-QUANTITY_TO_BUY:{}-BID:{}-ERROR", trader_name.clone(), kind_to_buy, quantity_to_buy, bid);
+        let record = LogRecord::new(Op::LockBuy).trader(&trader_name).kind(kind_to_buy).qty(quantity_to_buy).price(bid);
         let index = self.get_index_by_goodkind(&kind_to_buy);
-        let minimum_bid = self.get_buy_price(kind_to_buy.clone(), quantity_to_buy).unwrap();
 
         if quantity_to_buy < 0.0 {
-            print_metadata(logcode);
-            return Err(LockBuyError::NonPositiveQuantityToBuy { negative_quantity_to_buy: quantity_to_buy });
+            return self.reject(record, LockBuyError::NonPositiveQuantityToBuy { negative_quantity_to_buy: quantity_to_buy });
         }
         if bid < 0.0 {
-            print_metadata(logcode);
-            return Err(LockBuyError::NonPositiveBid { negative_bid: bid });
+            return self.reject(record, LockBuyError::NonPositiveBid { negative_bid: bid });
         }
-        if self.lock_buy[index].last == MAXLOCK as i32 {
-            print_metadata(logcode);
-            return Err(LockBuyError::MaxAllowedLocksReached);
+        if self.lock_buy[index].last == self.config.max_locks as i32 {
+            return self.reject(record, LockBuyError::MaxAllowedLocksReached);
         }
-        if (self.goods[index].get_qty() - self.locked_qty[index]) < quantity_to_buy {
-            print_metadata(logcode);
-            return Err(LockBuyError::InsufficientGoodQuantityAvailable { requested_good_kind: kind_to_buy.clone(), requested_good_quantity: quantity_to_buy, available_good_quantity: (self.goods[index].get_qty() - self.locked_qty[index]) });
+        //the trait has no error for halts and trader limits, the log still says which one
+        if let Err(e) = self.check_halt().and_then(|_| self.check_trader_limits(&trader_name, Mode::Buy, index, quantity_to_buy, bid)) {
+            self.stats.failed(&record.op, &e);
+            self.record(&record.failed(&e));
+            return Err(self.lock_buy_refusal(&e, &trader_name, index, quantity_to_buy, bid));
         }
+        if (self.goods[index].get_qty() - self.locked_qty[index]) < quantity_to_buy {
+            let available = self.goods[index].get_qty() - self.locked_qty[index];
+            return self.reject(record, LockBuyError::InsufficientGoodQuantityAvailable { requested_good_kind: kind_to_buy, requested_good_quantity: quantity_to_buy, available_good_quantity: available });
+        }
+        //the trader may owe less fee than the quote, which charges the highest rate anyone could pay
+        let (minimum_bid, fee) = match self.buy_cost(index, quantity_to_buy) {
+            Some(cost) => {
+                let fee = self.fee_on(cost, Some(&trader_name));
+                (cost + fee, fee)
+            }
+            None => {
+                let available = self.goods[index].get_qty() - self.locked_qty[index];
+                return self.reject(record, LockBuyError::InsufficientGoodQuantityAvailable { requested_good_kind: kind_to_buy, requested_good_quantity: quantity_to_buy, available_good_quantity: available });
+            }
+        };
         if minimum_bid > bid {
-            print_metadata(logcode);
-            return Err(LockBuyError::BidTooLow { requested_good_kind: kind_to_buy, requested_good_quantity: quantity_to_buy, low_bid: bid, lowest_acceptable_bid: minimum_bid });
+            return self.reject(record, LockBuyError::BidTooLow { requested_good_kind: kind_to_buy, requested_good_quantity: quantity_to_buy, low_bid: bid, lowest_acceptable_bid: minimum_bid });
         }
 
         let token = self.hash(&kind_to_buy, quantity_to_buy, bid, &trader_name);
 
-        self.lock_buy[index].insert(&token, quantity_to_buy, bid);
+        let slot = match self.lock_buy[index].insert(&token, quantity_to_buy, bid, fee) {
+            Some(slot) => slot,
+            None => return self.reject(record, LockBuyError::MaxAllowedLocksReached),
+        };
         self.lock_buy[index].last += 1;
         self.locked_qty[index] += quantity_to_buy;
 
-        self.token.insert(token.clone(), true);
+        self.token.insert(&token, index, slot, Mode::Buy, &trader_name);
+        self.traders.add_notional(&trader_name, bid);
 
-        let logcode = format!("LOCK_BUY-{}-KIND_TO_BUY:{}-QUANTITY_TO_BUY:{}-BID:{}-TOKEN:{}", trader_name.clone(), kind_to_buy, quantity_to_buy, bid, token.clone());
-        print_metadata(logcode);
+        self.stats.ok(&record.op);
+        self.record(&record.token(&token));
 
         Ok(token)
     }
@@ -329,42 +287,44 @@ impl Market for ZSE {
     fn buy(&mut self, token: String, cash: &mut Good) -> Result<Good, BuyError> {
         use unitn_market_2022::event::event::EventKind;
 
-        let (gk, pos) = self.get_kind_by_token(&token, Mode::Buy);
-        let index = self.get_index_by_goodkind(&gk);
+        //a token we can't use is refused before anything is broadcast
+        let (index, pos) = match self.token.live(&token, Mode::Buy) {
+            Ok(found) => found,
+            Err(state) => return self.reject(LogRecord::new(Op::Buy).token(&token), buy_token_error(token, state)),
+        };
+        let gk = self.goods[index].get_kind();
         let agreed_quantity = self.lock_buy[index].lock[pos].quantity;
         let agreed_price = self.lock_buy[index].lock[pos].price;
-        let logcode = format!("BUY-TOKEN:{}-ERROR", token.clone());
-
-        self.external = false;
-        self.on_event(Event { kind: EventKind::Bought, quantity: agreed_quantity, price: agreed_price, good_kind: gk.clone() });
+        let fee = self.lock_buy[index].lock[pos].fee;
+        let record = LogRecord::new(Op::Buy).token(&token).kind(gk).qty(agreed_quantity).price(agreed_price);
 
-        if !self.token.contains_key(&*token) {
-            print_metadata(logcode);
-            return Err(BuyError::UnrecognizedToken { unrecognized_token: token });
-        }
-        if self.token.contains_key(&*token) && !self.token[&token] {
-            print_metadata(logcode);
-            return Err(BuyError::ExpiredToken { expired_token: token });
-        }
+        //nor is a buy the trader can't pay for, a refused buy must not reprice or age anything
         if cash.get_kind() != GoodKind::EUR {
-            print_metadata(logcode);
-            return Err(BuyError::GoodKindNotDefault { non_default_good_kind: cash.get_kind() });
+            return self.reject(record, BuyError::GoodKindNotDefault { non_default_good_kind: cash.get_kind() });
         }
         if cash.get_qty() < agreed_price {
-            print_metadata(logcode);
-            return Err(BuyError::InsufficientGoodQuantity { contained_quantity: cash.get_qty(), pre_agreed_quantity: agreed_price });
+            return self.reject(record, BuyError::InsufficientGoodQuantity { contained_quantity: cash.get_qty(), pre_agreed_quantity: agreed_price });
+        }
+
+        self.publish(Event { kind: EventKind::Bought, quantity: agreed_quantity, price: agreed_price, good_kind: gk });
+
+        //the event itself may have been the tick that expired the lock
+        if let Err(state) = self.token.live(&token, Mode::Buy) {
+            return self.reject(record, buy_token_error(token, state));
         }
 
         let profit = cash.split(agreed_price);
         let _ = self.goods[0].merge(profit.unwrap());
+        self.collect_fee(&token, agreed_price - fee, fee);
+        self.rates.observe_trade(gk, agreed_quantity, agreed_price - fee);
 
         self.remove_lock(token.clone(), index, pos, Mode::Buy);
 
         let ret = self.goods[index].split(agreed_quantity).unwrap();
         self.locked_qty[index] -= agreed_quantity;
 
-        let logcode = format!("BUY-TOKEN:{}-OK", token.clone());
-        print_metadata(logcode);
+        self.stats.bought(&record.op, index, agreed_quantity, fee);
+        self.record(&record.fee(fee));
 
         Ok(ret)
     }
@@ -372,44 +332,51 @@ impl Market for ZSE {
     fn lock_sell(&mut self, kind_to_sell: GoodKind, quantity_to_sell: f32, offer: f32, trader_name: String) -> Result<String, LockSellError> {
         use unitn_market_2022::event::event::EventKind;
 
-        self.external = false;
-        self.on_event(Event { kind: EventKind::LockedSell, quantity: quantity_to_sell, price: offer, good_kind: kind_to_sell });
+        self.publish(Event { kind: EventKind::LockedSell, quantity: quantity_to_sell, price: offer, good_kind: kind_to_sell });
 
-        let logcode = format!("LOCK_SELL-{}-KIND_TO_SELL:{}-QUANTITY_TO_SELL:{}-OFFER:{}-ERROR", trader_name.clone(), kind_to_sell, quantity_to_sell, offer);
+        let record = LogRecord::new(Op::LockSell).trader(&trader_name).kind(kind_to_sell).qty(quantity_to_sell).price(offer);
         let index = self.get_index_by_goodkind(&kind_to_sell);
-        let acceptable_offer = self.get_sell_price(kind_to_sell.clone(), quantity_to_sell).unwrap();
 
         if quantity_to_sell < 0.0 {
-            print_metadata(logcode);
-            return Err(LockSellError::NonPositiveQuantityToSell { negative_quantity_to_sell: quantity_to_sell });
+            return self.reject(record, LockSellError::NonPositiveQuantityToSell { negative_quantity_to_sell: quantity_to_sell });
         }
         if offer < 0.0 {
-            print_metadata(logcode);
-            return Err(LockSellError::NonPositiveOffer { negative_offer: offer });
+            return self.reject(record, LockSellError::NonPositiveOffer { negative_offer: offer });
+        }
+        if self.lock_sell[index].last == self.config.max_locks as i32 {
+            return self.reject(record, LockSellError::MaxAllowedLocksReached);
         }
-        if self.lock_sell[index].last == MAXLOCK as i32 {
-            print_metadata(logcode);
-            return Err(LockSellError::MaxAllowedLocksReached);
+        if let Err(e) = self.check_halt().and_then(|_| self.check_trader_limits(&trader_name, Mode::Sell, index, quantity_to_sell, offer)) {
+            self.stats.failed(&record.op, &e);
+            self.record(&record.failed(&e));
+            return Err(self.lock_sell_refusal(&e, &trader_name, quantity_to_sell, kind_to_sell));
         }
-        if (self.goods[0].get_qty() - self.locked_qty[0]) < offer {
-            print_metadata(logcode);
-            return Err(LockSellError::InsufficientDefaultGoodQuantityAvailable { offered_good_kind: kind_to_sell, offered_good_quantity: quantity_to_sell, available_good_quantity: self.goods[0].get_qty() });
+        //we pay out offer + fee, so the fee has to fit in the payout and in our free EUR
+        let payout = self.sell_payout(index, quantity_to_sell);
+        let fee = self.fee_on(payout, Some(&trader_name));
+        if (self.goods[0].get_qty() - self.locked_qty[0]) < offer + fee {
+            let available = self.goods[0].get_qty();
+            return self.reject(record, LockSellError::InsufficientDefaultGoodQuantityAvailable { offered_good_kind: kind_to_sell, offered_good_quantity: quantity_to_sell, available_good_quantity: available });
         }
+        let acceptable_offer = (payout - fee).max(0.0);
         if acceptable_offer < offer {
-            print_metadata(logcode);
-            return Err(LockSellError::OfferTooHigh { offered_good_kind: kind_to_sell, offered_good_quantity: quantity_to_sell, high_offer: offer, highest_acceptable_offer: acceptable_offer });
+            return self.reject(record, LockSellError::OfferTooHigh { offered_good_kind: kind_to_sell, offered_good_quantity: quantity_to_sell, high_offer: offer, highest_acceptable_offer: acceptable_offer });
         }
 
         let token = self.hash(&kind_to_sell, quantity_to_sell, offer, &trader_name);
 
-        self.lock_sell[index].insert(&token, quantity_to_sell, offer);
+        let slot = match self.lock_sell[index].insert(&token, quantity_to_sell, offer, fee) {
+            Some(slot) => slot,
+            None => return self.reject(record, LockSellError::MaxAllowedLocksReached),
+        };
         self.lock_sell[index].last += 1;
-        self.locked_qty[0] += offer;
+        self.locked_qty[0] += offer + fee;
 
-        self.token.insert(token.clone(), true);
+        self.token.insert(&token, index, slot, Mode::Sell, &trader_name);
+        self.traders.add_notional(&trader_name, offer);
 
-        let logcode = format!("LOCK_SELL-{}-KIND_TO_SELL:{}-QUANTITY_TO_SELL:{}-OFFER:{}-TOKEN:{}", trader_name.clone(), kind_to_sell, quantity_to_sell, offer, token.clone());
-        print_metadata(logcode);
+        self.stats.ok(&record.op);
+        self.record(&record.token(&token));
 
         Ok(token)
     }
@@ -417,31 +384,27 @@ impl Market for ZSE {
     fn sell(&mut self, token: String, good: &mut Good) -> Result<Good, SellError> {
         use unitn_market_2022::event::event::EventKind;
 
-        let (gk, pos) = self.get_kind_by_token(&token, Mode::Sell);
-        let index = self.get_index_by_goodkind(&gk);
+        let (index, pos) = match self.token.live(&token, Mode::Sell) {
+            Ok(found) => found,
+            Err(state) => return self.reject(LogRecord::new(Op::Sell).token(&token), sell_token_error(token, state)),
+        };
+        let gk = self.goods[index].get_kind();
         let agreed_quantity = self.lock_sell[index].lock[pos].quantity;
         let agreed_price = self.lock_sell[index].lock[pos].price;
+        let fee = self.lock_sell[index].lock[pos].fee;
+        let record = LogRecord::new(Op::Sell).token(&token).kind(gk).qty(agreed_quantity).price(agreed_price);
 
-        self.external = false;
-        self.on_event(Event { kind: EventKind::Sold, quantity: agreed_quantity, price: agreed_price, good_kind: good.get_kind() });
-
-        let logcode = format!("SELL-TOKEN:{}-ERROR", token.clone());
-
-        if !self.token.contains_key(&*token) {
-            print_metadata(logcode);
-            return Err(SellError::UnrecognizedToken { unrecognized_token: token });
-        }
-        if self.token.contains_key(&*token) && !self.token[&token] {
-            print_metadata(logcode);
-            return Err(SellError::ExpiredToken { expired_token: token });
-        }
         if good.get_kind() != gk {
-            print_metadata(logcode);
-            return Err(SellError::WrongGoodKind { wrong_good_kind: good.get_kind(), pre_agreed_kind: gk });
+            return self.reject(record, SellError::WrongGoodKind { wrong_good_kind: good.get_kind(), pre_agreed_kind: gk });
         }
         if good.get_qty() < agreed_quantity {
-            print_metadata(logcode);
-            return Err(SellError::InsufficientGoodQuantity { contained_quantity: good.get_qty(), pre_agreed_quantity: agreed_quantity });
+            return self.reject(record, SellError::InsufficientGoodQuantity { contained_quantity: good.get_qty(), pre_agreed_quantity: agreed_quantity });
+        }
+
+        self.publish(Event { kind: EventKind::Sold, quantity: agreed_quantity, price: agreed_price, good_kind: gk });
+
+        if let Err(state) = self.token.live(&token, Mode::Sell) {
+            return self.reject(record, sell_token_error(token, state));
         }
 
         let profit = good.split(agreed_quantity);
@@ -450,10 +413,12 @@ impl Market for ZSE {
         self.remove_lock(token.clone(), index, pos, Mode::Sell);
 
         let ret = self.goods[0].split(agreed_price).unwrap();
-        self.locked_qty[0] -= agreed_price;
+        self.collect_fee(&token, agreed_price + fee, fee);
+        self.rates.observe_trade(gk, agreed_quantity, agreed_price + fee);
+        self.locked_qty[0] -= agreed_price + fee;
 
-        let logcode = format!("SELL-TOKEN:{}-OK", token.clone());
-        print_metadata(logcode);
+        self.stats.sold(&record.op, index, agreed_quantity, fee);
+        self.record(&record.fee(fee));
 
         Ok(ret)
     }
@@ -461,15 +426,145 @@ impl Market for ZSE {
 
 
 impl ZSE {
-    fn convert_to_eur(g: &Good) -> f32 {
-        match g.get_kind() {
-            GoodKind::EUR => g.get_qty(),
-            GoodKind::USD => g.get_qty() / DEFAULT_EUR_USD_EXCHANGE_RATE,
-            GoodKind::YEN => g.get_qty() / DEFAULT_EUR_YEN_EXCHANGE_RATE,
-            GoodKind::YUAN => g.get_qty() / DEFAULT_EUR_YUAN_EXCHANGE_RATE,
+    /// Same as `new_with_quantities`, but every limit of the market comes from `config`. Tokens are salted from `seed`.
+    pub fn with_config(eur: f32, yen: f32, usd: f32, yuan: f32, config: ZseConfig, seed: u64) -> Rc<RefCell<ZSE>> {
+        let pricing = config.pricing.build();
+        ZSE::with_strategy(eur, yen, usd, yuan, config, pricing, seed)
+    }
+
+    /// Like `with_config`, for pricing strategies that are not in `PricingKind`.
+    pub fn with_strategy(eur: f32, yen: f32, usd: f32, yuan: f32, config: ZseConfig, pricing: Box<dyn PricingStrategy>, seed: u64) -> Rc<RefCell<ZSE>> {
+        let mut market = ZSE::build(eur, usd, yen, yuan, pricing, config, true, StdRng::seed_from_u64(seed));
+        market.logger.record(&LogRecord::new(Op::Init([eur, usd, yen, yuan])));
+
+        Rc::new(RefCell::new(market))
+    }
+
+    /// Random split of `STARTING_CAPITAL` between the four goods, with the limits from `config`.
+    /// The split and the token salts both come from `seed`.
+    pub fn random_with_config(config: ZseConfig, seed: u64) -> Rc<RefCell<ZSE>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let tmp = ZSE::random_split(&mut rng);
+
+        //tmp is valued in EUR, the market and its log get quantities
+        let goods = [
+            tmp[0],
+            tmp[1] * DEFAULT_EUR_USD_EXCHANGE_RATE,
+            tmp[2] * DEFAULT_EUR_YEN_EXCHANGE_RATE,
+            tmp[3] * DEFAULT_EUR_YUAN_EXCHANGE_RATE,
+        ];
+        let pricing = config.pricing.build();
+        let mut market = ZSE::build(goods[0], goods[1], goods[2], goods[3], pricing, config, true, rng);
+        market.logger.record(&LogRecord::new(Op::Init(goods)));
+
+        Rc::new(RefCell::new(market))
+    }
+
+    /// Whole EUR amounts of `STARTING_CAPITAL`, one per good, EUR USD YEN YUAN.
+    pub fn random_split(rng: &mut impl Rng) -> [f32; 4] {
+        let mut remaining = STARTING_CAPITAL as i32;
+        let mut tmp = [0.0; 4];
+        let mut random_num;
+
+        for i in 0..3 {
+            random_num = rng.gen_range(0..remaining);
+            tmp[i] = random_num as f32;
+            remaining -= random_num;
+        }
+        tmp[3] = remaining as f32;
+        tmp
+    }
+
+    //fresh_log truncates the log file, otherwise we keep appending to it
+    fn build(eur: f32, usd: f32, yen: f32, yuan: f32, pricing: Box<dyn PricingStrategy>, config: ZseConfig, fresh_log: bool, rng: StdRng) -> ZSE {
+        ZSE {
+            goods: [
+                Good::new(GoodKind::EUR, eur),
+                Good::new(GoodKind::USD, usd),
+                Good::new(GoodKind::YEN, yen),
+                Good::new(GoodKind::YUAN, yuan),
+            ],
+            prices_sell: [
+                1.0,
+                DEFAULT_EUR_USD_EXCHANGE_RATE,
+                DEFAULT_EUR_YEN_EXCHANGE_RATE,
+                DEFAULT_EUR_YUAN_EXCHANGE_RATE,
+            ],
+            prices_buy: [
+                1.0,
+                DEFAULT_EUR_USD_EXCHANGE_RATE,
+                DEFAULT_EUR_YEN_EXCHANGE_RATE,
+                DEFAULT_EUR_YUAN_EXCHANGE_RATE,
+            ],
+            lock_buy: [Lock::new(config.max_locks), Lock::new(config.max_locks), Lock::new(config.max_locks), Lock::new(config.max_locks)],
+            lock_sell: [Lock::new(config.max_locks), Lock::new(config.max_locks), Lock::new(config.max_locks), Lock::new(config.max_locks)],
+            locked_qty: [0.0; 4],
+            token: TokenTable::new(config.token_retention),
+            traders: TraderBook::default(),
+            fee_account: 0.0,
+            rates: config.rates.build(),
+            markets: Vec::new(),
+            conversion_timer: [[0; 4]; 4],
+            rebalances: RebalanceHistory::default(),
+            breaker: BreakerState::default(),
+            stats: StatsBook::default(),
+            pricing,
+            logger: config.log_format.open(&config.log_path, fresh_log),
+            rng,
+            config,
+        }
+    }
+
+    fn note(&mut self, text: String) {
+        let record = LogRecord::new(Op::Note(text));
+        self.stats.ok(&record.op);
+        self.record(&record);
+    }
+
+    fn reject<T, E: Debug>(&mut self, record: LogRecord, error: E) -> Result<T, E> {
+        self.stats.failed(&record.op, &error);
+        self.record(&record.failed(&error));
+        Err(error)
+    }
+
+    //curve cost of buying quantity of goods[index], fees excluded
+    fn buy_cost(&self, index: usize, quantity: f32) -> Option<f32> {
+        self.config.curve.buy_cost(self.prices_buy[index], quantity, self.goods[index].get_qty())
+    }
+
+    //curve payout for selling quantity of goods[index] to us, fees excluded
+    fn sell_payout(&self, index: usize, quantity: f32) -> f32 {
+        self.config.curve.sell_payout(self.prices_sell[index], quantity, self.goods[index].get_qty())
+    }
+
+    fn fee_on(&self, notional: f32, trader: Option<&str>) -> f32 {
+        let volume = trader.map_or(0.0, |t| self.traders.volume(t));
+        self.config.fees.fee(notional, volume, trader, &self.config.makers)
+    }
+
+    //counts notional towards the volume tier of whoever holds token, then moves fee EUR from our stock to the fee account
+    fn collect_fee(&mut self, token: &str, notional: f32, fee: f32) {
+        if let Some(trader) = self.token_owner(token).map(|t| t.to_string()) {
+            self.traders.add_volume(&trader, notional);
+        }
+        if fee <= 0.0 {
+            return;
+        }
+        if self.goods[0].split(fee).is_ok() {
+            self.fee_account += fee;
         }
     }
 
+    /// Swaps the oracle `get_budget` and internal conversions value goods with, e.g. for a `MidPriceRates` over other markets.
+    pub fn set_rates(&mut self, rates: Box<dyn RateOracle>) {
+        self.rates = rates;
+    }
+
+    /// What the config asked to value goods with, `MidPrice` only works once `set_rates` got the other markets.
+    pub fn rate_source(&self) -> RateSource {
+        self.config.rates
+    }
+
     fn get_index_by_goodkind(&self, kind: &GoodKind) -> usize {
         return match *kind {
             GoodKind::EUR => 0,
@@ -479,46 +574,20 @@ impl ZSE {
         };
     }
 
-    fn get_kind_by_token(&self, token: &String, mode: Mode) -> (GoodKind, usize) {
-        let mut var = 0;
-        let mut index = 0;
-        let mut array = self.lock_buy.as_ref();
-
-        match mode {
-            Mode::Buy => {},
-            Mode::Sell => {
-                array = self.lock_sell.as_ref();
-            },
-        }
-        for i in 0..self.goods.len() {
-            for j in 0..MAXLOCK {
-                if array[i].lock[j].token == *token {
-                    var = i;
-                    index = j;
-                    break;
-                }
-            }
-        }
-
-        (self.goods[var].get_kind(), index)
-    }
-
-    fn hash(&self, v1: &GoodKind, v2: f32, v3: f32, v4: &String) -> String {
+    fn hash(&mut self, v1: &GoodKind, v2: f32, v3: f32, v4: &String) -> String {
         use sha256::digest;
-        use rand::Rng;
 
         let a = digest(self.get_index_by_goodkind(&v1).to_string());
         let b = digest(v2.to_string());
         let c = digest(v3.to_string());
         let d = digest(v4.clone());
-        let mut rng = rand::thread_rng();
-        let salt = rng.gen::<u32>();
+        let salt = self.rng.gen::<u32>();
 
         digest(format!("{}{}{}{}{}", a, b, c, d, salt))
     }
 
     fn remove_lock(&mut self, token: String, index: usize, pos: usize, mode: Mode) {
-        self.token.insert(token, false);
+        self.token.retire(&token, TokenState::Executed);
         let _ = match mode {
             Mode::Buy => {
                 self.lock_buy[index].last -= 1;
@@ -531,68 +600,6 @@ impl ZSE {
         };
     }
 
-    fn increment_lock_counter_and_reset(&mut self) {
-        for i in 0..4 {
-            for j in 0..MAXLOCK {
-                if self.lock_buy[i].lock[j].token != "".to_string() {
-                    self.lock_buy[i].lock[j].lock_counter += 1;
-                }
-                if self.lock_sell[i].lock[j].token != "".to_string() {
-                    self.lock_sell[i].lock[j].lock_counter += 1;
-                }
-                if self.lock_buy[i].lock[j].lock_counter >= MAXTIME {
-                    self.token.insert(self.lock_buy[i].lock[j].token.clone(), false);
-                    self.lock_buy[i].lock[j].remove();
-                }
-                if self.lock_sell[i].lock[j].lock_counter >= MAXTIME {
-                    self.token.insert(self.lock_sell[i].lock[j].token.clone(), false);
-                    self.lock_sell[i].lock[j].remove();
-                }
-            }
-        }
-    }
-
-    fn internal_conversion(&mut self) {
-        use rand::Rng;
-
-        let mut max_good = self.goods[0].clone();
-        let mut min_good = self.goods[0].clone();
-        for g in &self.goods {
-            if g.get_qty() > max_good.get_qty() {
-                max_good = g.clone();
-            }
-            if g.get_qty() < min_good.get_qty() {
-                min_good = g.clone();
-            }
-        }
-        if min_good.get_qty() < 14000.0 && max_good.get_kind() != min_good.get_kind() {
-            if self.conversion_timer[self.get_index_by_goodkind(&max_good.get_kind())][self.get_index_by_goodkind(&min_good.get_kind())] == 0 {
-                let conversion_rate_from = match max_good.get_kind() {
-                    GoodKind::EUR => 1.0,
-                    GoodKind::USD => DEFAULT_EUR_USD_EXCHANGE_RATE,
-                    GoodKind::YEN => DEFAULT_EUR_YEN_EXCHANGE_RATE,
-                    GoodKind::YUAN => DEFAULT_EUR_YUAN_EXCHANGE_RATE,
-                };
-                let conversion_rate_to = match max_good.get_kind() {
-                    GoodKind::EUR => 1.0,
-                    GoodKind::USD => DEFAULT_EUR_USD_EXCHANGE_RATE,
-                    GoodKind::YEN => DEFAULT_EUR_YEN_EXCHANGE_RATE,
-                    GoodKind::YUAN => DEFAULT_EUR_YUAN_EXCHANGE_RATE,
-                };
-
-                let mut value_to_convert = 0.0;
-                if max_good.get_qty() > 10000.0 {
-                    value_to_convert = 10000.0;
-                    self.conversion_timer[self.get_index_by_goodkind(&max_good.get_kind())][self.get_index_by_goodkind(&min_good.get_kind())] = 100;
-                }
-
-                let _ = self.goods[self.get_index_by_goodkind(&max_good.get_kind())].split(value_to_convert);
-                let new_good = Good::new(min_good.get_kind(), value_to_convert * conversion_rate_to / conversion_rate_from);
-                let _ = self.goods[self.get_index_by_goodkind(&min_good.get_kind())].merge(new_good);
-            }
-        }
-    }
-
     fn decrement_conversion_timer(&mut self) {
         for i in 0..4 {
             for j in 0..4 {
@@ -611,6 +618,7 @@ impl Contract {
             token: "".to_string(),
             quantity: 0.0,
             price: 0.0,
+            fee: 0.0,
             lock_counter: 0,
         }
     }
@@ -619,61 +627,45 @@ impl Contract {
         self.token = "".to_string();
         self.quantity = 0.0;
         self.price = 0.0;
+        self.fee = 0.0;
         self.lock_counter = 0;
     }
 }
 
 impl Lock {
-    fn new() -> Self {
+    fn new(size: usize) -> Self {
         Lock {
-            lock: [Contract::new(), Contract::new(), Contract::new()],
+            lock: (0..size).map(|_| Contract::new()).collect(),
             last: 0,
         }
     }
 
-    fn insert(&mut self, token: &String, qty: f32, price: f32) {
-        for i in 0..MAXLOCK {
+    //slot the contract ended up in, None if every slot is taken
+    fn insert(&mut self, token: &String, qty: f32, price: f32, fee: f32) -> Option<usize> {
+        for i in 0..self.lock.len() {
             if self.lock[i].token == "".to_string() {
                 self.lock[i].token = token.clone();
                 self.lock[i].quantity = qty;
                 self.lock[i].price = price;
-                return;
+                self.lock[i].fee = fee;
+                return Some(i);
             }
         }
+        None
     }
 }
 
-fn init_file() {
-    use std::fs::OpenOptions;
-
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(PATH_LOG);
-    match file {
-        Ok(file) => file,
-        Err(_) => panic!("Error opening / creating file"),
-    };
+//cancelled tokens and tokens we never issued (or already forgot) look the same to a trader
+fn buy_token_error(token: String, state: Option<TokenState>) -> BuyError {
+    match state {
+        Some(TokenState::Expired) | Some(TokenState::Executed) => BuyError::ExpiredToken { expired_token: token },
+        _ => BuyError::UnrecognizedToken { unrecognized_token: token },
+    }
 }
 
-fn print_metadata(buffer: String) {
-    use std::fs::OpenOptions;
-    use std::io::Write;
-    use chrono::Local;
-
-    let file = OpenOptions::new().append(true).open(PATH_LOG);
-    match file {
-        Ok(mut file) => {
-            let date = Local::now();
-            let atm = date.format("%Y:%m:%d:%H:%M:%S:%3f");
-            let s = format!("ZSE|{}|{}\n", atm, buffer);
-            let write = file.write_all(s.as_bytes());
-            match write {
-                Ok(_) => {}
-                Err(_) => println!("Error writing to file"),
-            }
-        }
-        Err(_) => panic!("Error opening file"),
-    };
+fn sell_token_error(token: String, state: Option<TokenState>) -> SellError {
+    match state {
+        Some(TokenState::Expired) | Some(TokenState::Executed) => SellError::ExpiredToken { expired_token: token },
+        _ => SellError::UnrecognizedToken { unrecognized_token: token },
+    }
 }
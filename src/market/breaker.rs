@@ -0,0 +1,305 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use unitn_market_2022::good::good_kind::GoodKind;
+
+use super::log::{LogRecord, Op};
+use super::traders::ZseTraderError;
+use super::ZSE;
+
+//halts and resumes kept for `ZSE::breaker_events`, the log has all of them
+const BREAKER_HISTORY: usize = 100;
+
+/// Conditions that halt trading at ZSE, `None` turns a breaker off.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CircuitBreakers {
+    /// largest relative change of a buy or sell price allowed within `window` events
+    pub max_move: Option<f32>,
+    pub window: usize,
+    /// unlocked quantity of each good we never want to go below, EUR USD YEN YUAN.
+    /// Trips when the quantity falls below the floor or further down, not again for staying where a halt left it
+    pub inventory_floor: [Option<f32>; 4],
+    /// market days (`Wait` events) a halt lasts, 0 lifts it at the next one
+    pub halt_days: u32,
+}
+
+impl Default for CircuitBreakers {
+    fn default() -> Self {
+        CircuitBreakers {
+            max_move: None,
+            window: 50,
+            inventory_floor: [None; 4],
+            halt_days: 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HaltReason {
+    /// buy price of `kind` went from `from` to `to` within the window
+    PriceMove { kind: GoodKind, from: f32, to: f32 },
+    SellPriceMove { kind: GoodKind, from: f32, to: f32 },
+    InventoryFloor { kind: GoodKind, quantity: f32, floor: f32 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BreakerEvent {
+    Halted { reason: HaltReason, days: u32 },
+    Resumed,
+}
+
+impl Display for HaltReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HaltReason::PriceMove { kind, from, to } => write!(f, "{} price moved from {} to {}", kind, from, to),
+            HaltReason::SellPriceMove { kind, from, to } => write!(f, "{} sell price moved from {} to {}", kind, from, to),
+            HaltReason::InventoryFloor { kind, quantity, floor } => write!(f, "{} down to {}, floor is {}", kind, quantity, floor),
+        }
+    }
+}
+
+impl HaltReason {
+    /// `price_move USD 1.1 0.8`, `sell_price_move USD 1.0 0.7` or `inventory_floor YEN 90 100`, used by logs and snapshots.
+    pub fn encode(&self) -> String {
+        match self {
+            HaltReason::PriceMove { kind, from, to } => format!("price_move {} {} {}", kind, from, to),
+            HaltReason::SellPriceMove { kind, from, to } => format!("sell_price_move {} {} {}", kind, from, to),
+            HaltReason::InventoryFloor { kind, quantity, floor } => format!("inventory_floor {} {} {}", kind, quantity, floor),
+        }
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        if fields.len() != 4 {
+            return None;
+        }
+        let kind = match fields[1] {
+            "EUR" => GoodKind::EUR,
+            "USD" => GoodKind::USD,
+            "YEN" => GoodKind::YEN,
+            "YUAN" => GoodKind::YUAN,
+            _ => return None,
+        };
+        let a = fields[2].parse().ok()?;
+        let b = fields[3].parse().ok()?;
+        match fields[0] {
+            "price_move" => Some(HaltReason::PriceMove { kind, from: a, to: b }),
+            "sell_price_move" => Some(HaltReason::SellPriceMove { kind, from: a, to: b }),
+            "inventory_floor" => Some(HaltReason::InventoryFloor { kind, quantity: a, floor: b }),
+            _ => None,
+        }
+    }
+}
+
+/// Recent prices and the halt in force, if any.
+#[derive(Default)]
+pub(super) struct BreakerState {
+    //buy and sell prices after each of the last `window` events, oldest first
+    pub prices: VecDeque<([f32; 4], [f32; 4])>,
+    //reason and market days left
+    pub halt: Option<(HaltReason, u32)>,
+    //unlocked quantities at the previous event, None before the first one
+    pub free: Option<[f32; 4]>,
+    pub(super) events: VecDeque<BreakerEvent>,
+    subscribers: Vec<Sender<BreakerEvent>>,
+}
+
+impl BreakerState {
+    //keeps it for `breaker_events` and tells every subscriber still listening
+    fn announce(&mut self, event: BreakerEvent) {
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+        if self.events.len() == BREAKER_HISTORY {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
+
+impl ZSE {
+    /// Why trading is halted, `None` while it is open.
+    pub fn halted(&self) -> Option<&HaltReason> {
+        self.breaker.halt.as_ref().map(|(reason, _)| reason)
+    }
+
+    /// Most recent halts and resumes, oldest first.
+    pub fn breaker_events(&self) -> impl Iterator<Item = &BreakerEvent> {
+        self.breaker.events.iter()
+    }
+
+    /// Every halt and resume from now on, as it happens.
+    pub fn subscribe_breakers(&mut self) -> Receiver<BreakerEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.breaker.subscribers.push(tx);
+        rx
+    }
+
+    /// Refuses new locks while halted.
+    pub(super) fn check_halt(&self) -> Result<(), ZseTraderError> {
+        match self.halted() {
+            Some(reason) => Err(ZseTraderError::Halted { reason: reason.clone() }),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs after every event, once prices are updated. Nothing is checked while already halted.
+    pub(super) fn check_breakers(&mut self) {
+        //followed while halted too, so a resume only looks at what moved after it
+        let free: [f32; 4] = std::array::from_fn(|i| self.goods[i].get_qty() - self.locked_qty[i]);
+        let before = self.breaker.free.replace(free);
+        if self.breaker.halt.is_some() {
+            return;
+        }
+        let breakers = &self.config.breakers;
+
+        self.breaker.prices.push_back((self.prices_buy, self.prices_sell));
+        while self.breaker.prices.len() > breakers.window.max(1) {
+            self.breaker.prices.pop_front();
+        }
+
+        let mut reason = None;
+        if let (Some(max), Some((oldest_buy, oldest_sell))) = (breakers.max_move, self.breaker.prices.front()) {
+            for i in 1..4 {
+                let kind = self.goods[i].get_kind();
+                let (from, to) = (oldest_buy[i], self.prices_buy[i]);
+                if from > 0.0 && (to / from - 1.0).abs() > max {
+                    reason = Some(HaltReason::PriceMove { kind, from, to });
+                    break;
+                }
+                let (from, to) = (oldest_sell[i], self.prices_sell[i]);
+                if from > 0.0 && (to / from - 1.0).abs() > max {
+                    reason = Some(HaltReason::SellPriceMove { kind, from, to });
+                    break;
+                }
+            }
+        }
+        if reason.is_none() {
+            for i in 0..4 {
+                let quantity = free[i];
+                let fell = before.map_or(true, |before| quantity < before[i]);
+                match breakers.inventory_floor[i] {
+                    Some(floor) if quantity < floor && fell => {
+                        reason = Some(HaltReason::InventoryFloor { kind: self.goods[i].get_kind(), quantity, floor });
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(reason) = reason {
+            let days = breakers.halt_days;
+            let record = LogRecord::new(Op::Halt { reason: reason.clone(), days });
+            self.stats.ok(&record.op);
+            self.record(&record);
+            self.breaker.halt = Some((reason.clone(), days));
+            self.breaker.announce(BreakerEvent::Halted { reason, days });
+        }
+    }
+
+    /// Counts a market day off the halt and lifts it once none are left.
+    pub(super) fn breaker_new_day(&mut self) {
+        let days_left = match &mut self.breaker.halt {
+            Some((_, days)) if *days > 0 => {
+                *days -= 1;
+                *days
+            }
+            Some(_) => 0,
+            None => return,
+        };
+        if days_left == 0 {
+            self.breaker.halt = None;
+            //the move that halted us must not halt us again
+            self.breaker.prices.clear();
+            self.stats.ok(&Op::Resume);
+            self.record(&LogRecord::new(Op::Resume));
+            self.breaker.announce(BreakerEvent::Resumed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use unitn_market_2022::event::event::{Event, EventKind};
+    use unitn_market_2022::event::notifiable::Notifiable;
+    use unitn_market_2022::good::good_kind::GoodKind;
+    use unitn_market_2022::market::{LockBuyError, Market};
+
+    use super::super::{LogFormat, PricingKind, ZseConfig, ZseTraderError, ZSE};
+    use super::{BreakerEvent, CircuitBreakers, HaltReason};
+
+    fn market(breakers: CircuitBreakers) -> Rc<RefCell<ZSE>> {
+        let config = ZseConfig::builder().breakers(breakers).pricing(PricingKind::Dumping).log_format(LogFormat::Off).build();
+        ZSE::with_config(10000.0, 10000.0, 10000.0, 10000.0, config, 0)
+    }
+
+    //an event from another market
+    fn tell(m: &mut ZSE, kind: EventKind, quantity: f32, price: f32) {
+        m.on_event(Event { kind, good_kind: GoodKind::USD, quantity, price });
+    }
+
+    //new locks are refused, through the trait as nothing available
+    fn assert_refused(m: &mut ZSE) {
+        let res = m.lock_buy(GoodKind::YEN, 10.0, 1000.0, "tester".to_string());
+        assert!(matches!(res, Err(LockBuyError::InsufficientGoodQuantityAvailable { available_good_quantity, .. }) if available_good_quantity == 0.0), "{:?}", res);
+        let res = m.try_lock_sell(GoodKind::YEN, 10.0, 0.0, "tester".to_string());
+        assert!(matches!(res, Err(ZseTraderError::Halted { .. })), "{:?}", res);
+    }
+
+    //halt_days Waits lift the halt, then a few quiet events must not bring it back
+    fn assert_resumes(m: &mut ZSE, halt_days: u32) {
+        for _ in 1..halt_days {
+            tell(m, EventKind::Wait, 0.0, 0.0);
+            assert!(m.halted().is_some());
+        }
+        tell(m, EventKind::Wait, 0.0, 0.0);
+        assert!(m.halted().is_none());
+        for _ in 0..5 {
+            tell(m, EventKind::Bought, 0.0, 0.0);
+        }
+        assert!(m.halted().is_none());
+        assert!(matches!(m.breaker_events().last(), Some(BreakerEvent::Resumed)));
+        let bid = m.get_buy_price(GoodKind::YEN, 10.0).unwrap();
+        m.lock_buy(GoodKind::YEN, 10.0, bid, "tester".to_string()).unwrap();
+    }
+
+    #[test]
+    fn price_move_halts_until_the_days_pass() {
+        let market = market(CircuitBreakers { max_move: Some(0.1), halt_days: 2, ..Default::default() });
+        let mut m = market.borrow_mut();
+        let events = m.subscribe_breakers();
+        tell(&mut m, EventKind::Bought, 0.0, 0.0);
+        assert!(m.halted().is_none());
+
+        //someone sold USD at half our price, we undercut them
+        let price = m.prices_buy[1];
+        tell(&mut m, EventKind::Bought, 100.0, 100.0 * price / 2.0);
+        assert!(matches!(m.halted(), Some(HaltReason::PriceMove { kind: GoodKind::USD, .. })), "{:?}", m.halted());
+        assert!(matches!(events.try_recv(), Ok(BreakerEvent::Halted { days: 2, .. })));
+        assert_refused(&mut m);
+
+        assert_resumes(&mut m, 2);
+        assert!(matches!(events.try_recv(), Ok(BreakerEvent::Resumed)));
+    }
+
+    #[test]
+    fn inventory_floor_halts_once_per_fall() {
+        let mut floor = [None; 4];
+        floor[1] = Some(9950.0);
+        let market = market(CircuitBreakers { inventory_floor: floor, halt_days: 1, ..Default::default() });
+        let mut m = market.borrow_mut();
+
+        let bid = m.get_buy_price(GoodKind::USD, 100.0).unwrap();
+        m.lock_buy(GoodKind::USD, 100.0, bid, "tester".to_string()).unwrap();
+        //the lock is checked at the next event
+        tell(&mut m, EventKind::Bought, 0.0, 0.0);
+        assert!(matches!(m.halted(), Some(HaltReason::InventoryFloor { kind: GoodKind::USD, .. })), "{:?}", m.halted());
+        assert_refused(&mut m);
+
+        //still below the floor, but it did not fall any further
+        assert_resumes(&mut m, 1);
+    }
+}
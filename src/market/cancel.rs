@@ -0,0 +1,179 @@
+use std::fmt::{Display, Formatter};
+
+use super::log::{LogRecord, Op};
+use super::{Mode, TokenState, ZSE};
+
+/// Lets the holder of a lock give it back before it expires, `trader_name` has to be the one that locked it.
+/// Implemented by ZSE, traders reach it through `MarketRegistry::cancel_lock`.
+pub trait CancelLock {
+    fn cancel_lock(&mut self, token: &str, trader_name: &str) -> Result<(), CancelLockError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CancelLockError {
+    UnrecognizedToken { unrecognized_token: String },
+    ExpiredToken { expired_token: String },
+    AlreadyExecuted { executed_token: String },
+    AlreadyCancelled { cancelled_token: String },
+    NotTokenOwner { token: String, trader: String },
+}
+
+impl Display for CancelLockError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CancelLockError::UnrecognizedToken { unrecognized_token } => write!(f, "unrecognized token {}", unrecognized_token),
+            CancelLockError::ExpiredToken { expired_token } => write!(f, "token {} already expired", expired_token),
+            CancelLockError::AlreadyExecuted { executed_token } => write!(f, "token {} already executed", executed_token),
+            CancelLockError::AlreadyCancelled { cancelled_token } => write!(f, "token {} already cancelled", cancelled_token),
+            CancelLockError::NotTokenOwner { token, trader } => write!(f, "token {} was not issued to {}", token, trader),
+        }
+    }
+}
+
+impl CancelLock for ZSE {
+    /// Frees the slot and the reserved quantity, a cancelled token is rejected by `buy`/`sell` as unrecognized.
+    fn cancel_lock(&mut self, token: &str, trader_name: &str) -> Result<(), CancelLockError> {
+        let record = LogRecord::new(Op::Cancel).trader(trader_name).token(token);
+
+        //nobody learns anything about a token that isn't theirs
+        if self.token_owner(token).map_or(false, |owner| owner != trader_name) {
+            return self.reject(record, CancelLockError::NotTokenOwner { token: token.to_string(), trader: trader_name.to_string() });
+        }
+
+        let (mode, index, pos) = match self.token.get(token).map(|e| (e.state, e.mode, e.index, e.slot)) {
+            None => return self.reject(record, CancelLockError::UnrecognizedToken { unrecognized_token: token.to_string() }),
+            Some((TokenState::Expired, ..)) => return self.reject(record, CancelLockError::ExpiredToken { expired_token: token.to_string() }),
+            Some((TokenState::Executed, ..)) => return self.reject(record, CancelLockError::AlreadyExecuted { executed_token: token.to_string() }),
+            Some((TokenState::Cancelled, ..)) => return self.reject(record, CancelLockError::AlreadyCancelled { cancelled_token: token.to_string() }),
+            Some((TokenState::Active, mode, index, pos)) => (mode, index, pos),
+        };
+        let (kind, quantity, price, fee) = {
+            let contract = match mode {
+                Mode::Buy => &self.lock_buy[index].lock[pos],
+                Mode::Sell => &self.lock_sell[index].lock[pos],
+            };
+            (self.goods[index].get_kind(), contract.quantity, contract.price, contract.fee)
+        };
+
+        match mode {
+            Mode::Buy => {
+                self.lock_buy[index].last -= 1;
+                self.lock_buy[index].lock[pos].remove();
+                self.locked_qty[index] -= quantity;
+            }
+            Mode::Sell => {
+                self.lock_sell[index].last -= 1;
+                self.lock_sell[index].lock[pos].remove();
+                self.locked_qty[0] -= price + fee;
+            }
+        }
+        self.token.retire(token, TokenState::Cancelled);
+
+        self.stats.ok(&record.op);
+        self.record(&record.kind(kind).qty(quantity).price(price));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use unitn_market_2022::event::event::{Event, EventKind};
+    use unitn_market_2022::event::notifiable::Notifiable;
+    use unitn_market_2022::good::good::Good;
+    use unitn_market_2022::good::good_kind::GoodKind;
+    use unitn_market_2022::market::{BuyError, Market, SellError};
+
+    use super::super::{ExpiryClock, FeeSchedule, LogFormat, ZseConfig, ZSE};
+    use super::{CancelLock, CancelLockError};
+
+    fn market() -> Rc<RefCell<ZSE>> {
+        let config = ZseConfig::builder()
+            .fees(FeeSchedule::Flat(5.0))
+            .lock_expiry(2)
+            .expiry_clock(ExpiryClock::Events)
+            .log_format(LogFormat::Off)
+            .build();
+        ZSE::with_config(10000.0, 10000.0, 10000.0, 10000.0, config, 0)
+    }
+
+    fn lock_buy(m: &mut ZSE, trader: &str) -> String {
+        let bid = m.get_buy_price(GoodKind::USD, 10.0).unwrap();
+        m.lock_buy(GoodKind::USD, 10.0, bid, trader.to_string()).unwrap()
+    }
+
+    fn lock_sell(m: &mut ZSE, trader: &str) -> String {
+        let offer = m.get_sell_price(GoodKind::USD, 10.0).unwrap();
+        m.lock_sell(GoodKind::USD, 10.0, offer, trader.to_string()).unwrap()
+    }
+
+    //trades elsewhere, enough to expire every open lock
+    fn expire_all(m: &mut ZSE) {
+        for _ in 0..4 {
+            m.on_event(Event { kind: EventKind::Bought, good_kind: GoodKind::EUR, quantity: 0.0, price: 0.0 });
+        }
+    }
+
+    #[test]
+    fn cancel_frees_the_slot_and_the_reserved_quantity() {
+        let market = market();
+        let mut m = market.borrow_mut();
+
+        let token = lock_buy(&mut m, "tester");
+        assert_eq!((m.lock_buy[1].last, m.locked_qty[1]), (1, 10.0));
+        m.cancel_lock(&token, "tester").unwrap();
+        assert_eq!((m.lock_buy[1].last, m.locked_qty[1]), (0, 0.0));
+
+        //sell locks reserve the offer and the fee we pay on top of it
+        let offer = m.get_sell_price(GoodKind::USD, 10.0).unwrap();
+        let token = lock_sell(&mut m, "tester");
+        assert_eq!((m.lock_sell[1].last, m.locked_qty[0]), (1, offer + 5.0));
+        m.cancel_lock(&token, "tester").unwrap();
+        assert_eq!((m.lock_sell[1].last, m.locked_qty[0]), (0, 0.0));
+    }
+
+    #[test]
+    fn every_refusal_has_its_variant() {
+        let market = market();
+        let mut m = market.borrow_mut();
+
+        assert!(matches!(m.cancel_lock("nope", "tester"), Err(CancelLockError::UnrecognizedToken { .. })));
+
+        let token = lock_buy(&mut m, "tester");
+        assert!(matches!(m.cancel_lock(&token, "someone else"), Err(CancelLockError::NotTokenOwner { .. })));
+        m.cancel_lock(&token, "tester").unwrap();
+        assert!(matches!(m.cancel_lock(&token, "tester"), Err(CancelLockError::AlreadyCancelled { .. })));
+
+        let token = lock_buy(&mut m, "tester");
+        let price = m.lock_buy[1].lock.iter().find(|c| c.token == token).unwrap().price;
+        m.buy(token.clone(), &mut Good::new(GoodKind::EUR, price)).unwrap();
+        assert!(matches!(m.cancel_lock(&token, "tester"), Err(CancelLockError::AlreadyExecuted { .. })));
+
+        let token = lock_sell(&mut m, "tester");
+        expire_all(&mut m);
+        assert!(matches!(m.cancel_lock(&token, "tester"), Err(CancelLockError::ExpiredToken { .. })));
+    }
+
+    #[test]
+    fn cancelled_tokens_are_unrecognized_expired_ones_expired() {
+        let market = market();
+        let mut m = market.borrow_mut();
+
+        let cancelled_buy = lock_buy(&mut m, "tester");
+        let cancelled_sell = lock_sell(&mut m, "tester");
+        m.cancel_lock(&cancelled_buy, "tester").unwrap();
+        m.cancel_lock(&cancelled_sell, "tester").unwrap();
+        let expired_buy = lock_buy(&mut m, "tester");
+        let expired_sell = lock_sell(&mut m, "tester");
+        expire_all(&mut m);
+
+        let mut cash = Good::new(GoodKind::EUR, 10000.0);
+        let mut usd = Good::new(GoodKind::USD, 100.0);
+        assert!(matches!(m.buy(cancelled_buy, &mut cash), Err(BuyError::UnrecognizedToken { .. })));
+        assert!(matches!(m.sell(cancelled_sell, &mut usd), Err(SellError::UnrecognizedToken { .. })));
+        assert!(matches!(m.buy(expired_buy, &mut cash), Err(BuyError::ExpiredToken { .. })));
+        assert!(matches!(m.sell(expired_sell, &mut usd), Err(SellError::ExpiredToken { .. })));
+    }
+}
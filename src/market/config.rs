@@ -0,0 +1,374 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::oracle::RateSource;
+
+use super::breaker::CircuitBreakers;
+use super::curve::PriceCurve;
+use super::expiry::ExpiryClock;
+use super::fees::FeeSchedule;
+use super::log::LogFormat;
+use super::pricing::PricingKind;
+use super::traders::TraderLimits;
+use super::treasury::TreasuryTargets;
+
+/// Per-instance limits of a ZSE market.
+/// Build it with `ZseConfig::builder()` or load it from a TOML file with `ZseConfig::load`.
+#[derive(Clone, Debug)]
+pub struct ZseConfig {
+    /// locks a single good can hold on each side at the same time
+    pub max_locks: usize,
+    /// ticks of `expiry_clock` a lock survives before it expires
+    pub lock_expiry: i32,
+    pub expiry_clock: ExpiryClock,
+    /// inventory allocation the internal conversions steer towards
+    pub treasury: TreasuryTargets,
+    pub breakers: CircuitBreakers,
+    /// events to wait before converting between the same two goods again
+    pub conversion_cooldown: i32,
+    /// executed, expired and cancelled tokens remembered before the oldest are forgotten
+    pub token_retention: usize,
+    /// limits of every trader without an entry in `trader_limits_for`
+    pub trader_limits: TraderLimits,
+    pub trader_limits_for: HashMap<String, TraderLimits>,
+    pub fees: FeeSchedule,
+    /// what `get_budget` and internal conversions value goods with, the constants unless `ema` or `mid_price` is asked for
+    pub rates: RateSource,
+    /// traders paying the maker rate of `FeeSchedule::MakerTaker`
+    pub makers: HashSet<String>,
+    pub log_path: String,
+    pub log_format: LogFormat,
+    pub pricing: PricingKind,
+    pub curve: PriceCurve,
+}
+
+pub struct ZseConfigBuilder {
+    config: ZseConfig,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String),
+    Syntax { line: usize, text: String },
+    UnknownKey { line: usize, key: String },
+    UnknownTable { line: usize, table: String },
+    InvalidValue { line: usize, key: String, value: String },
+}
+
+impl Default for ZseConfig {
+    fn default() -> Self {
+        ZseConfig {
+            max_locks: 3,
+            lock_expiry: 15,
+            expiry_clock: ExpiryClock::Events,
+            treasury: TreasuryTargets::default(),
+            breakers: CircuitBreakers::default(),
+            conversion_cooldown: 100,
+            token_retention: 1000,
+            trader_limits: TraderLimits::default(),
+            trader_limits_for: HashMap::new(),
+            fees: FeeSchedule::default(),
+            rates: RateSource::Static,
+            makers: HashSet::new(),
+            log_path: "log_ZSE.txt".to_string(),
+            log_format: LogFormat::Text,
+            pricing: PricingKind::Dumping,
+            curve: PriceCurve::default(),
+        }
+    }
+}
+
+impl ZseConfig {
+    pub fn builder() -> ZseConfigBuilder {
+        ZseConfigBuilder {
+            config: ZseConfig::default(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(e.to_string()))?;
+        Self::from_toml_str(&text)
+    }
+
+    /// Reads the flat subset of TOML we need: `key = value` pairs, an optional `[zse]` table and `#` comments,
+    /// no inline tables, multi-line arrays or escapes in strings.
+    /// Limits of a single trader go in a `[trader."name"]` table, the `trader_*` keys of `[zse]` apply to everyone else.
+    /// Missing keys keep their default, unknown keys and tables and out of range values are errors.
+    pub fn from_toml_str(text: &str) -> Result<Self, ConfigError> {
+        let mut config = ZseConfig::default();
+        //trader whose table we are in, None for [zse]
+        let mut section: Option<String> = None;
+
+        for (n, raw) in text.lines().enumerate() {
+            let line_number = n + 1;
+            let line = strip_comment(raw).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "[zse]" {
+                section = None;
+                continue;
+            }
+            if let Some(name) = line.strip_prefix("[trader.").and_then(|l| l.strip_suffix(']')) {
+                let name = unquote(name).unwrap_or(name);
+                config.trader_limits_for.entry(name.to_string()).or_default();
+                section = Some(name.to_string());
+                continue;
+            }
+            if line.starts_with('[') {
+                return Err(ConfigError::UnknownTable { line: line_number, table: line.to_string() });
+            }
+
+            let (key, value) = match line.split_once('=') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => return Err(ConfigError::Syntax { line: line_number, text: raw.to_string() }),
+            };
+            let invalid = || ConfigError::InvalidValue { line: line_number, key: key.to_string(), value: value.to_string() };
+
+            if let Some(trader) = &section {
+                if key == "maker" {
+                    match value {
+                        "true" => config.makers.insert(trader.clone()),
+                        "false" => config.makers.remove(trader),
+                        _ => return Err(invalid()),
+                    };
+                    continue;
+                }
+                let limits = config.trader_limits_for.entry(trader.clone()).or_default();
+                match set_limit(limits, key, value) {
+                    Some(true) => continue,
+                    Some(false) => return Err(invalid()),
+                    None => return Err(ConfigError::UnknownKey { line: line_number, key: key.to_string() }),
+                }
+            }
+            if let Some(limit) = key.strip_prefix("trader_") {
+                match set_limit(&mut config.trader_limits, limit, value) {
+                    Some(true) => continue,
+                    Some(false) => return Err(invalid()),
+                    None => {}
+                }
+            }
+
+            match key {
+                "max_locks" => config.max_locks = at_least(value, 1).ok_or_else(invalid)?,
+                "lock_expiry" => config.lock_expiry = at_least(value, 1).ok_or_else(invalid)?,
+                "expiry_clock" => {
+                    let name = unquote(value).ok_or_else(invalid)?;
+                    config.expiry_clock = ExpiryClock::from_name(name).ok_or_else(invalid)?;
+                }
+                "target_weights" => {
+                    let weights = parse_four(value).ok_or_else(invalid)?;
+                    if weights.iter().sum::<f32>() <= 0.0 {
+                        return Err(invalid());
+                    }
+                    config.treasury.weights = weights;
+                }
+                "rebalance_tolerance" => config.treasury.tolerance = fraction(value).ok_or_else(invalid)?,
+                "rebalance_cost" => config.treasury.cost = fraction(value).ok_or_else(invalid)?,
+                "breaker_max_move" => config.breakers.max_move = Some(at_least(value, 0.0).ok_or_else(invalid)?),
+                "breaker_window" => config.breakers.window = at_least(value, 1).ok_or_else(invalid)?,
+                "breaker_floor" => {
+                    let floors = parse_four(value).ok_or_else(invalid)?;
+                    //a floor of 0 never trips
+                    config.breakers.inventory_floor = floors.map(|f| if f > 0.0 { Some(f) } else { None });
+                }
+                "halt_days" => config.breakers.halt_days = value.parse().map_err(|_| invalid())?,
+                "conversion_cooldown" => config.conversion_cooldown = at_least(value, 0).ok_or_else(invalid)?,
+                "token_retention" => config.token_retention = at_least(value, 1).ok_or_else(invalid)?,
+                "fees" => config.fees = FeeSchedule::parse(unquote(value).ok_or_else(invalid)?).ok_or_else(invalid)?,
+                "rates" => {
+                    let name = unquote(value).ok_or_else(invalid)?;
+                    config.rates = RateSource::from_name(name).ok_or_else(invalid)?;
+                }
+                "log_path" => config.log_path = unquote(value).ok_or_else(invalid)?.to_string(),
+                "log_format" => {
+                    let name = unquote(value).ok_or_else(invalid)?;
+                    config.log_format = LogFormat::from_name(name).ok_or_else(invalid)?;
+                }
+                "pricing" => {
+                    let name = unquote(value).ok_or_else(invalid)?;
+                    config.pricing = PricingKind::from_name(name).ok_or_else(invalid)?;
+                }
+                "curve" => {
+                    let name = unquote(value).ok_or_else(invalid)?;
+                    config.curve = PriceCurve::from_name(name).ok_or_else(invalid)?;
+                }
+                _ => return Err(ConfigError::UnknownKey { line: line_number, key: key.to_string() }),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl ZseConfigBuilder {
+    pub fn max_locks(mut self, max_locks: usize) -> Self {
+        self.config.max_locks = max_locks;
+        self
+    }
+
+    pub fn lock_expiry(mut self, lock_expiry: i32) -> Self {
+        self.config.lock_expiry = lock_expiry;
+        self
+    }
+
+    pub fn expiry_clock(mut self, expiry_clock: ExpiryClock) -> Self {
+        self.config.expiry_clock = expiry_clock;
+        self
+    }
+
+    pub fn treasury(mut self, treasury: TreasuryTargets) -> Self {
+        self.config.treasury = treasury;
+        self
+    }
+
+    pub fn breakers(mut self, breakers: CircuitBreakers) -> Self {
+        self.config.breakers = breakers;
+        self
+    }
+
+    pub fn conversion_cooldown(mut self, conversion_cooldown: i32) -> Self {
+        self.config.conversion_cooldown = conversion_cooldown;
+        self
+    }
+
+    pub fn token_retention(mut self, token_retention: usize) -> Self {
+        self.config.token_retention = token_retention;
+        self
+    }
+
+    pub fn trader_limits(mut self, limits: TraderLimits) -> Self {
+        self.config.trader_limits = limits;
+        self
+    }
+
+    pub fn limits_for(mut self, trader: &str, limits: TraderLimits) -> Self {
+        self.config.trader_limits_for.insert(trader.to_string(), limits);
+        self
+    }
+
+    pub fn fees(mut self, fees: FeeSchedule) -> Self {
+        self.config.fees = fees;
+        self
+    }
+
+    pub fn rates(mut self, rates: RateSource) -> Self {
+        self.config.rates = rates;
+        self
+    }
+
+    pub fn maker(mut self, trader: &str) -> Self {
+        self.config.makers.insert(trader.to_string());
+        self
+    }
+
+    pub fn log_path(mut self, log_path: &str) -> Self {
+        self.config.log_path = log_path.to_string();
+        self
+    }
+
+    pub fn log_format(mut self, log_format: LogFormat) -> Self {
+        self.config.log_format = log_format;
+        self
+    }
+
+    pub fn pricing(mut self, pricing: PricingKind) -> Self {
+        self.config.pricing = pricing;
+        self
+    }
+
+    pub fn curve(mut self, curve: PriceCurve) -> Self {
+        self.config.curve = curve;
+        self
+    }
+
+    pub fn build(self) -> ZseConfig {
+        self.config
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "cannot read config: {}", e),
+            ConfigError::Syntax { line, text } => write!(f, "line {}: expected `key = value`, found `{}`", line, text),
+            ConfigError::UnknownKey { line, key } => write!(f, "line {}: unknown key `{}`", line, key),
+            ConfigError::UnknownTable { line, table } => write!(f, "line {}: unknown table `{}`", line, table),
+            ConfigError::InvalidValue { line, key, value } => write!(f, "line {}: invalid value `{}` for `{}`", line, value, key),
+        }
+    }
+}
+
+//None if key is not a limit, Some(false) if the value does not parse
+fn set_limit(limits: &mut TraderLimits, key: &str, value: &str) -> Option<bool> {
+    let ok = match key {
+        "max_open_locks" => value.parse().map(|v| limits.max_open_locks = Some(v)).is_ok(),
+        "max_locks_per_good" => value.parse().map(|v| limits.max_locks_per_good = Some(v)).is_ok(),
+        "max_daily_notional" => at_least(value, 0.0).map(|v| limits.max_daily_notional = Some(v)).is_some(),
+        "max_inventory_share" => fraction(value).map(|v| limits.max_inventory_share = Some(v)).is_some(),
+        _ => return None,
+    };
+    Some(ok)
+}
+
+//None if value does not parse or is below min
+fn at_least<T: FromStr + PartialOrd>(value: &str, min: T) -> Option<T> {
+    value.parse().ok().filter(|v| *v >= min)
+}
+
+//0 to 1 included
+fn fraction(value: &str) -> Option<f32> {
+    at_least(value, 0.0).filter(|v| *v <= 1.0)
+}
+
+//`[0.4, 0.2, 0.2, 0.2]`, EUR USD YEN YUAN, no negatives
+fn parse_four(value: &str) -> Option<[f32; 4]> {
+    let inner = value.strip_prefix('[')?.strip_suffix(']')?;
+    let weights: Vec<f32> = inner.split(',').map(|w| w.trim().parse().ok()).collect::<Option<_>>()?;
+    if weights.len() != 4 || weights.iter().any(|w| *w < 0.0) {
+        return None;
+    }
+    Some([weights[0], weights[1], weights[2], weights[3]])
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn unquote(value: &str) -> Option<&str> {
+    value.strip_prefix('"')?.strip_suffix('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        for text in ["max_locks = 0", "lock_expiry = 0", "lock_expiry = -3", "token_retention = 0", "rebalance_tolerance = 1.5"] {
+            assert!(matches!(ZseConfig::from_toml_str(text), Err(ConfigError::InvalidValue { line: 1, .. })), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_tables() {
+        assert!(matches!(ZseConfig::from_toml_str("max_lock = 3"), Err(ConfigError::UnknownKey { line: 1, .. })));
+        assert!(matches!(ZseConfig::from_toml_str("[zse]\n[market]"), Err(ConfigError::UnknownTable { line: 2, .. })));
+    }
+
+    #[test]
+    fn reads_limits() {
+        let config = ZseConfig::from_toml_str("[zse]\nmax_locks = 5 # more room\nlock_expiry = 20").unwrap();
+        assert_eq!(config.max_locks, 5);
+        assert_eq!(config.lock_expiry, 20);
+    }
+}
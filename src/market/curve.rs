@@ -0,0 +1,128 @@
+/// How the quoted unit price degrades with the size of an order compared to our inventory.
+/// Both `get_buy_price`/`get_sell_price` and the lock validation go through it, so a quote is always honoured.
+/// `Linear` is the original pricing and what `ZseConfig` defaults to, the binary runs `Tiered` unless told otherwise.
+#[derive(Clone, Debug)]
+pub enum PriceCurve {
+    /// `price * quantity`, every unit costs the same
+    Linear,
+    /// x * y = k pool between the good and EUR, the whole stock of a good is never for sale
+    ConstantProduct,
+    /// `(share of inventory, extra spread)` pairs sorted by share, orders above the last share pay the last spread
+    Tiered(Vec<(f32, f32)>),
+}
+
+impl Default for PriceCurve {
+    fn default() -> Self {
+        PriceCurve::Linear
+    }
+}
+
+impl PriceCurve {
+    pub fn default_tiers() -> Self {
+        PriceCurve::Tiered(vec![(0.01, 0.0), (0.05, 0.005), (0.2, 0.02), (1.0, 0.05)])
+    }
+
+    /// `linear`, `constant_product` or `tiered`, which gets `default_tiers`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(PriceCurve::Linear),
+            "constant_product" => Some(PriceCurve::ConstantProduct),
+            "tiered" => Some(PriceCurve::default_tiers()),
+            _ => None,
+        }
+    }
+
+    /// EUR a trader pays for `quantity` units when we hold `available` of them.
+    /// None when the curve cannot serve that much, which only `ConstantProduct` does, at `available` or more.
+    pub fn buy_cost(&self, unit_price: f32, quantity: f32, available: f32) -> Option<f32> {
+        match self {
+            PriceCurve::Linear => Some(unit_price * quantity),
+            PriceCurve::ConstantProduct => {
+                if quantity >= available {
+                    return None;
+                }
+                Some(unit_price * quantity * available / (available - quantity))
+            }
+            PriceCurve::Tiered(tiers) => {
+                let share = share_of(quantity, available);
+                Some(unit_price * quantity * (1.0 + spread_for(tiers, share)))
+            }
+        }
+    }
+
+    /// EUR we pay for `quantity` units when we already hold `held` of them.
+    pub fn sell_payout(&self, unit_price: f32, quantity: f32, held: f32) -> f32 {
+        match self {
+            PriceCurve::Linear => unit_price * quantity,
+            PriceCurve::ConstantProduct => {
+                if held + quantity <= 0.0 {
+                    return unit_price * quantity;
+                }
+                unit_price * quantity * held / (held + quantity)
+            }
+            PriceCurve::Tiered(tiers) => {
+                let share = share_of(quantity, held);
+                unit_price * quantity * (1.0 - spread_for(tiers, share)).max(0.0)
+            }
+        }
+    }
+}
+
+fn share_of(quantity: f32, inventory: f32) -> f32 {
+    if inventory <= 0.0 {
+        f32::INFINITY
+    } else {
+        quantity / inventory
+    }
+}
+
+fn spread_for(tiers: &[(f32, f32)], share: f32) -> f32 {
+    for (limit, spread) in tiers {
+        if share <= *limit {
+            return *spread;
+        }
+    }
+    tiers.last().map_or(0.0, |(_, spread)| *spread)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use unitn_market_2022::good::good_kind::GoodKind;
+    use unitn_market_2022::market::{LockBuyError, Market};
+
+    use super::super::{LogFormat, ZseConfig, ZSE};
+    use super::PriceCurve;
+
+    fn market(curve: PriceCurve) -> Rc<RefCell<ZSE>> {
+        let config = ZseConfig::builder().curve(curve).log_format(LogFormat::Off).build();
+        ZSE::with_config(10000.0, 10000.0, 10000.0, 10000.0, config, 0)
+    }
+
+    #[test]
+    fn half_the_stock_costs_more_than_the_flat_rate() {
+        for curve in [PriceCurve::ConstantProduct, PriceCurve::default_tiers()] {
+            let market = market(curve.clone());
+            let mut m = market.borrow_mut();
+            let quantity = m.goods[1].get_qty() / 2.0;
+            let flat = m.prices_buy[1] * quantity;
+            assert!(m.buy_cost(1, quantity).unwrap() > flat, "{:?}", curve);
+
+            let res = m.lock_buy(GoodKind::USD, quantity, flat, "tester".to_string());
+            assert!(matches!(res, Err(LockBuyError::BidTooLow { .. })), "{:?}", curve);
+
+            let quote = m.get_buy_price(GoodKind::USD, quantity).unwrap();
+            assert!(m.lock_buy(GoodKind::USD, quantity, quote, "tester".to_string()).is_ok(), "{:?}", curve);
+        }
+    }
+
+    #[test]
+    fn linear_charges_the_flat_rate() {
+        let market = market(PriceCurve::Linear);
+        let m = market.borrow();
+        let quantity = m.goods[1].get_qty() / 2.0;
+        assert_eq!(m.buy_cost(1, quantity), Some(m.prices_buy[1] * quantity));
+    }
+}
@@ -0,0 +1,177 @@
+use unitn_market_2022::event::event::{Event, EventKind};
+
+use super::log::{LogRecord, Op};
+use super::{Mode, TokenState, ZSE};
+
+/// What makes a lock older.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExpiryClock {
+    /// every event the market sees, ours or from subscribed markets
+    Events,
+    /// only market days, i.e. `Wait` events
+    Days,
+}
+
+impl ExpiryClock {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "events" => Some(ExpiryClock::Events),
+            "days" => Some(ExpiryClock::Days),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExpiryClock::Events => "events",
+            ExpiryClock::Days => "days",
+        }
+    }
+
+    fn ticks_on(&self, event: &Event) -> bool {
+        match self {
+            ExpiryClock::Events => true,
+            ExpiryClock::Days => matches!(event.kind, EventKind::Wait),
+        }
+    }
+}
+
+impl ZSE {
+    /// Ages every live lock by one tick of the configured clock and expires the ones past `lock_expiry`.
+    pub(super) fn age_locks(&mut self, event: &Event) {
+        if !self.config.expiry_clock.ticks_on(event) {
+            return;
+        }
+
+        let mut expired = Vec::new();
+        for (mode, locks) in [(Mode::Buy, &mut self.lock_buy), (Mode::Sell, &mut self.lock_sell)] {
+            for (i, lock) in locks.iter_mut().enumerate() {
+                for (j, contract) in lock.lock.iter_mut().enumerate() {
+                    if contract.token.is_empty() {
+                        continue;
+                    }
+                    contract.lock_counter += 1;
+                    if contract.lock_counter >= self.config.lock_expiry {
+                        expired.push((mode, i, j));
+                    }
+                }
+            }
+        }
+
+        for (mode, i, j) in expired {
+            self.expire_contract(mode, i, j);
+        }
+    }
+
+    //gives back the slot and the reserved quantity together, so last/locked_qty always match the live contracts
+    fn expire_contract(&mut self, mode: Mode, index: usize, pos: usize) {
+        let lock = match mode {
+            Mode::Buy => &mut self.lock_buy[index],
+            Mode::Sell => &mut self.lock_sell[index],
+        };
+        let contract = &mut lock.lock[pos];
+        let token = contract.token.clone();
+        let (quantity, price, fee) = (contract.quantity, contract.price, contract.fee);
+        contract.remove();
+        lock.last -= 1;
+
+        match mode {
+            //a buy lock reserves the good, a sell lock reserves the EUR we promised
+            Mode::Buy => self.locked_qty[index] -= quantity,
+            Mode::Sell => self.locked_qty[0] -= price + fee,
+        }
+        self.token.retire(&token, TokenState::Expired);
+
+        let record = LogRecord::new(Op::Expire)
+            .kind(self.goods[index].get_kind())
+            .qty(quantity)
+            .price(price)
+            .token(&token);
+        self.stats.expired();
+        self.record(&record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use unitn_market_2022::event::event::{Event, EventKind};
+    use unitn_market_2022::event::notifiable::Notifiable;
+    use unitn_market_2022::good::good_kind::GoodKind;
+    use unitn_market_2022::market::Market;
+
+    use super::super::{TokenState, ZseConfig, ZSE};
+    use super::ExpiryClock;
+
+    const EXPIRY: i32 = 3;
+
+    fn market(clock: ExpiryClock) -> Rc<RefCell<ZSE>> {
+        let path = std::env::temp_dir().join(format!("zse_expiry_{}.txt", clock.name()));
+        let config = ZseConfig::builder()
+            .lock_expiry(EXPIRY)
+            .expiry_clock(clock)
+            .log_path(path.to_str().unwrap())
+            .build();
+        ZSE::with_config(10000.0, 10000.0, 10000.0, 10000.0, config, 0)
+    }
+
+    //an event from another market
+    fn tell(market: &Rc<RefCell<ZSE>>, kind: EventKind) {
+        market.borrow_mut().on_event(Event { kind, good_kind: GoodKind::EUR, quantity: 0.0, price: 0.0 });
+    }
+
+    fn inventory(market: &ZSE) -> Vec<(f32, f32)> {
+        (0..4).map(|i| (market.goods[i].get_qty(), market.goods[i].get_qty() - market.locked_qty[i])).collect()
+    }
+
+    //a buy and a sell lock on USD, aged by `age` until both expire
+    fn lock_and_expire(market: &Rc<RefCell<ZSE>>, age: impl Fn(&Rc<RefCell<ZSE>>)) {
+        let before = inventory(&market.borrow());
+
+        let bid = market.borrow().get_buy_price(GoodKind::USD, 10.0).unwrap();
+        let buy_token = market.borrow_mut().lock_buy(GoodKind::USD, 10.0, bid, "tester".to_string()).unwrap();
+        let offer = market.borrow().get_sell_price(GoodKind::USD, 10.0).unwrap();
+        let sell_token = market.borrow_mut().lock_sell(GoodKind::USD, 10.0, offer, "tester".to_string()).unwrap();
+        {
+            let m = market.borrow();
+            assert_eq!(m.lock_buy[1].last, 1);
+            assert_eq!(m.lock_sell[1].last, 1);
+            assert!(m.locked_qty[1] > 0.0 && m.locked_qty[0] > 0.0);
+        }
+
+        for _ in 0..EXPIRY {
+            age(market);
+        }
+
+        let m = market.borrow();
+        assert_eq!(m.lock_buy[1].last, 0);
+        assert_eq!(m.lock_sell[1].last, 0);
+        assert_eq!(m.locked_qty, [0.0; 4]);
+        assert_eq!(inventory(&m), before);
+        for token in [buy_token, sell_token] {
+            assert_eq!(m.token.get(&token).map(|entry| entry.state), Some(TokenState::Expired));
+        }
+    }
+
+    #[test]
+    fn events_clock_conserves_inventory() {
+        let market = market(ExpiryClock::Events);
+        lock_and_expire(&market, |m| tell(m, EventKind::Bought));
+    }
+
+    #[test]
+    fn days_clock_conserves_inventory() {
+        let market = market(ExpiryClock::Days);
+        lock_and_expire(&market, |m| {
+            //trades elsewhere don't age locks on this clock
+            for _ in 0..EXPIRY {
+                tell(m, EventKind::Bought);
+            }
+            assert_eq!(m.borrow().lock_buy[1].last, 1);
+            assert_eq!(m.borrow().lock_sell[1].last, 1);
+            tell(m, EventKind::Wait);
+        });
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashSet;
+
+/// What ZSE charges on top of the spread, always in EUR.
+/// Buy fees are paid on top of the cost, sell fees are kept out of the payout.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FeeSchedule {
+    Free,
+    /// same amount on every trade
+    Flat(f32),
+    /// fraction of the notional
+    Percentage(f32),
+    /// (minimum EUR the trader already traded with us, fraction), the highest tier reached applies, ascending
+    Tiered(Vec<(f32, f32)>),
+    /// fraction of the notional, makers are the traders listed in `ZseConfig::makers`
+    MakerTaker { maker: f32, taker: f32 },
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        FeeSchedule::Free
+    }
+}
+
+impl FeeSchedule {
+    /// Fee on a trade of `notional` EUR by a trader who already traded `volume` EUR with us.
+    /// Quotes have no trader and pay the highest rate anyone could, so a lock at the quote is never refused for its fee.
+    pub fn fee(&self, notional: f32, volume: f32, trader: Option<&str>, makers: &HashSet<String>) -> f32 {
+        let fee = match self {
+            FeeSchedule::Free => 0.0,
+            FeeSchedule::Flat(amount) => *amount,
+            FeeSchedule::Percentage(rate) => notional * rate,
+            FeeSchedule::Tiered(tiers) if trader.is_none() => notional * tiers.iter().map(|(_, rate)| *rate).fold(0.0, f32::max),
+            FeeSchedule::MakerTaker { maker, taker } if trader.is_none() => notional * maker.max(*taker),
+            FeeSchedule::Tiered(tiers) => {
+                let rate = tiers
+                    .iter()
+                    .take_while(|(min, _)| volume >= *min)
+                    .last()
+                    .map_or(0.0, |(_, rate)| *rate);
+                notional * rate
+            }
+            FeeSchedule::MakerTaker { maker, taker } => match trader {
+                Some(name) if makers.contains(name) => notional * maker,
+                _ => notional * taker,
+            },
+        };
+        fee.max(0.0)
+    }
+
+    /// Reads `none`, `flat 0.5`, `percentage 0.002`, `tiered 0:0.003 10000:0.002` or `maker_taker 0.001 0.003`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut fields = text.split_whitespace();
+        let schedule = match fields.next()? {
+            "none" => FeeSchedule::Free,
+            "flat" => FeeSchedule::Flat(fields.next()?.parse().ok()?),
+            "percentage" => FeeSchedule::Percentage(fields.next()?.parse().ok()?),
+            "tiered" => {
+                let mut tiers = Vec::new();
+                for tier in fields.by_ref() {
+                    let (min, rate) = tier.split_once(':')?;
+                    tiers.push((min.parse().ok()?, rate.parse().ok()?));
+                }
+                FeeSchedule::Tiered(tiers)
+            }
+            "maker_taker" => FeeSchedule::MakerTaker {
+                maker: fields.next()?.parse().ok()?,
+                taker: fields.next()?.parse().ok()?,
+            },
+            _ => return None,
+        };
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(schedule)
+    }
+
+    /// Inverse of `parse`.
+    pub fn encode(&self) -> String {
+        match self {
+            FeeSchedule::Free => "none".to_string(),
+            FeeSchedule::Flat(amount) => format!("flat {}", amount),
+            FeeSchedule::Percentage(rate) => format!("percentage {}", rate),
+            FeeSchedule::Tiered(tiers) => {
+                let tiers: Vec<String> = tiers.iter().map(|(min, rate)| format!("{}:{}", min, rate)).collect();
+                format!("tiered {}", tiers.join(" ")).trim_end().to_string()
+            }
+            FeeSchedule::MakerTaker { maker, taker } => format!("maker_taker {} {}", maker, taker),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unitn_market_2022::good::good::Good;
+    use unitn_market_2022::good::good_kind::GoodKind;
+    use unitn_market_2022::market::Market;
+
+    use super::super::{LogFormat, TreasuryTargets, ZseConfig, ZSE};
+    use super::FeeSchedule;
+
+    fn schedules() -> Vec<FeeSchedule> {
+        vec![
+            FeeSchedule::Free,
+            FeeSchedule::Flat(3.0),
+            FeeSchedule::Percentage(0.01),
+            //rates falling with volume, and the odd schedule where they rise
+            FeeSchedule::Tiered(vec![(0.0, 0.01), (100.0, 0.001)]),
+            FeeSchedule::Tiered(vec![(0.0, 0.001), (100.0, 0.01)]),
+            FeeSchedule::MakerTaker { maker: 0.0005, taker: 0.003 },
+            FeeSchedule::MakerTaker { maker: 0.003, taker: 0.0005 },
+        ]
+    }
+
+    #[test]
+    fn a_lock_at_the_quote_always_passes() {
+        for fees in schedules() {
+            let treasury = TreasuryTargets { weights: [0.0; 4], ..Default::default() };
+            let config = ZseConfig::builder().fees(fees.clone()).maker("mm").treasury(treasury).log_format(LogFormat::Off).build();
+            let market = ZSE::with_config(10000.0, 10000.0, 10000.0, 10000.0, config, 0);
+            let mut m = market.borrow_mut();
+
+            for trader in ["mm", "taker"] {
+                //past the 100 EUR tier first
+                let bid = m.get_buy_price(GoodKind::USD, 200.0).unwrap();
+                let token = m.lock_buy(GoodKind::USD, 200.0, bid, trader.to_string()).unwrap();
+                m.buy(token, &mut Good::new(GoodKind::EUR, bid)).unwrap();
+
+                let bid = m.get_buy_price(GoodKind::USD, 10.0).unwrap();
+                let res = m.lock_buy(GoodKind::USD, 10.0, bid, trader.to_string());
+                assert!(res.is_ok(), "{:?} {} {:?}", fees, trader, res);
+                let offer = m.get_sell_price(GoodKind::USD, 10.0).unwrap();
+                let res = m.lock_sell(GoodKind::USD, 10.0, offer, trader.to_string());
+                assert!(res.is_ok(), "{:?} {} {:?}", fees, trader, res);
+            }
+        }
+    }
+}
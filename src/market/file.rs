@@ -0,0 +1,159 @@
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::rc::Rc;
+
+use super::{ZseConfig, ZSE};
+
+/// Why a market file was rejected, lines are 1-based.
+#[derive(Debug)]
+pub enum ZseFileError {
+    Io(String),
+    MissingQuantity { line: usize, good: String },
+    InvalidQuantity { line: usize, value: String },
+    NegativeQuantity { line: usize, quantity: f32 },
+    UnknownGood { line: usize, good: String },
+    DuplicateGood { line: usize, good: String, first_line: usize },
+    TrailingData { line: usize, text: String },
+}
+
+impl Display for ZseFileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZseFileError::Io(e) => write!(f, "cannot read market file: {}", e),
+            ZseFileError::MissingQuantity { line, good } => write!(f, "line {}: no quantity for {}", line, good),
+            ZseFileError::InvalidQuantity { line, value } => write!(f, "line {}: `{}` is not a number", line, value),
+            ZseFileError::NegativeQuantity { line, quantity } => write!(f, "line {}: negative quantity {}", line, quantity),
+            ZseFileError::UnknownGood { line, good } => write!(f, "line {}: unknown good `{}`", line, good),
+            ZseFileError::DuplicateGood { line, good, first_line } => write!(f, "line {}: {} already set on line {}", line, good, first_line),
+            ZseFileError::TrailingData { line, text } => write!(f, "line {}: unexpected `{}`", line, text),
+        }
+    }
+}
+
+impl ZSE {
+    /// Strict version of `new_file`, tokens are salted from entropy like every trait built market.
+    /// Expects one `KIND quantity` pair per line, `#` starts a comment, blank lines are skipped and missing goods start at 0.
+    pub fn try_new_file(path: &str) -> Result<Rc<RefCell<ZSE>>, ZseFileError> {
+        let [eur, usd, yen, yuan] = read_quantities(path)?;
+
+        Ok(ZSE::with_config(eur, yen, usd, yuan, ZseConfig::default(), rand::random()))
+    }
+
+    /// What `new_file` does: the market in `path`, or a random one with a note in its log saying why.
+    pub(super) fn file_or_random(path: &str, config: ZseConfig, seed: u64) -> Rc<RefCell<ZSE>> {
+        match read_quantities(path) {
+            Ok([eur, usd, yen, yuan]) => ZSE::with_config(eur, yen, usd, yuan, config, seed),
+            Err(e) => {
+                let market = ZSE::random_with_config(config, seed);
+                market.borrow_mut().note(format!("NEW_FILE-PATH:{}-FALLBACK_RANDOM:{}", path, e));
+                market
+            }
+        }
+    }
+}
+
+fn read_quantities(path: &str) -> Result<[f32; 4], ZseFileError> {
+    let file = File::open(path).map_err(|e| ZseFileError::Io(e.to_string()))?;
+    parse_quantities(BufReader::new(file))
+}
+
+/// Returns EUR USD YEN YUAN.
+fn parse_quantities(reader: impl BufRead) -> Result<[f32; 4], ZseFileError> {
+    let mut quantities = [0.0; 4];
+    let mut seen_on: [Option<usize>; 4] = [None; 4];
+
+    for (n, line) in reader.lines().enumerate() {
+        let line_number = n + 1;
+        let line = line.map_err(|e| ZseFileError::Io(e.to_string()))?;
+        let content = match line.split_once('#') {
+            Some((before, _)) => before,
+            None => line.as_str(),
+        };
+
+        let mut split = content.split_whitespace();
+        let good = match split.next() {
+            Some(good) => good,
+            None => continue,
+        };
+        let index = match good {
+            "EUR" => 0,
+            "USD" => 1,
+            "YEN" => 2,
+            "YUAN" => 3,
+            _ => return Err(ZseFileError::UnknownGood { line: line_number, good: good.to_string() }),
+        };
+        let value = split.next().ok_or_else(|| ZseFileError::MissingQuantity { line: line_number, good: good.to_string() })?;
+        let quantity = value
+            .parse::<f32>()
+            .map_err(|_| ZseFileError::InvalidQuantity { line: line_number, value: value.to_string() })?;
+        if !quantity.is_finite() {
+            return Err(ZseFileError::InvalidQuantity { line: line_number, value: value.to_string() });
+        }
+        if quantity < 0.0 {
+            return Err(ZseFileError::NegativeQuantity { line: line_number, quantity });
+        }
+        let rest: Vec<&str> = split.collect();
+        if !rest.is_empty() {
+            return Err(ZseFileError::TrailingData { line: line_number, text: rest.join(" ") });
+        }
+        if let Some(first_line) = seen_on[index] {
+            return Err(ZseFileError::DuplicateGood { line: line_number, good: good.to_string(), first_line });
+        }
+
+        seen_on[index] = Some(line_number);
+        quantities[index] = quantity;
+    }
+
+    Ok(quantities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ZseConfig, ZSE};
+    use super::{parse_quantities, ZseFileError};
+
+    fn parse(text: &str) -> Result<[f32; 4], ZseFileError> {
+        parse_quantities(text.as_bytes())
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let text = "# starting goods\n\nEUR 100 # cash\n  \nYEN 2.5\n";
+        assert_eq!(parse(text).unwrap(), [100.0, 0.0, 2.5, 0.0]);
+    }
+
+    #[test]
+    fn errors_carry_the_line() {
+        let text = "EUR 1\n# comment\n\nUSD 2\nEUR 3";
+        assert!(matches!(parse(text), Err(ZseFileError::DuplicateGood { line: 5, first_line: 1, .. })));
+        assert!(matches!(parse("\nUSD 2 3"), Err(ZseFileError::TrailingData { line: 2, .. })));
+        assert!(matches!(parse("GBP 2"), Err(ZseFileError::UnknownGood { line: 1, .. })));
+        assert!(matches!(parse("EUR 1\nYUAN"), Err(ZseFileError::MissingQuantity { line: 2, .. })));
+    }
+
+    #[test]
+    fn rejects_negative_and_non_finite_values() {
+        assert!(matches!(parse("EUR -1"), Err(ZseFileError::NegativeQuantity { line: 1, .. })));
+        for value in ["NaN", "inf", "-inf", "ten"] {
+            let text = format!("USD {}", value);
+            assert!(matches!(parse(&text), Err(ZseFileError::InvalidQuantity { line: 1, .. })), "{}", value);
+        }
+    }
+
+    #[test]
+    fn missing_file_falls_back_with_a_note() {
+        let log = std::env::temp_dir().join("zse_file_fallback_log.txt");
+        let missing = std::env::temp_dir().join("zse_file_that_is_not_there.txt");
+        let config = ZseConfig::builder().log_path(log.to_str().unwrap()).build();
+
+        let market = ZSE::file_or_random(missing.to_str().unwrap(), config, 0);
+        assert_eq!(market.borrow().stats().succeeded.get("note"), Some(&1));
+        //dropping the market flushes its log
+        drop(market);
+
+        let text = std::fs::read_to_string(&log).unwrap();
+        assert!(text.lines().any(|l| l.contains("NEW_FILE-PATH:") && l.contains("-FALLBACK_RANDOM:")), "{}", text);
+    }
+}
@@ -0,0 +1,456 @@
+use std::fmt::Debug;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use chrono::Local;
+use unitn_market_2022::good::good_kind::GoodKind;
+
+use super::breaker::HaltReason;
+use super::treasury::Rebalance;
+
+/// What a log record is about.
+#[derive(Clone, Debug)]
+pub enum Op {
+    /// starting quantities, EUR USD YEN YUAN
+    Init([f32; 4]),
+    LockBuy,
+    Buy,
+    LockSell,
+    Sell,
+    /// a lock given back by its holder
+    Cancel,
+    /// a lock nobody used in time, the market released it
+    Expire,
+    /// part of a buy lock executed, qty and price are the filled part
+    PartialBuy,
+    PartialSell,
+    /// an internal conversion between two of our goods
+    Rebalance(Rebalance),
+    /// a circuit breaker tripped, new locks are refused for `days` market days
+    Halt { reason: HaltReason, days: u32 },
+    Resume,
+    /// anything that is not a trade, e.g. a snapshot restore
+    Note(String),
+}
+
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    Ok,
+    /// name of the error variant returned to the trader
+    Error(String),
+}
+
+/// One record per market operation.
+#[derive(Clone, Debug)]
+pub struct LogRecord {
+    pub op: Op,
+    pub trader: Option<String>,
+    pub kind: Option<GoodKind>,
+    pub qty: Option<f32>,
+    pub price: Option<f32>,
+    pub token: Option<String>,
+    /// EUR that went to the fee account
+    pub fee: Option<f32>,
+    pub outcome: Outcome,
+}
+
+//paths a market of this process already truncated, later markets append to them
+static TRUNCATED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+//every sink of this process gets its own id, so records of markets sharing a file can be told apart
+static NEXT_INSTANCE: AtomicU32 = AtomicU32::new(1);
+
+//records kept in the buffer before they are forced to disk, a market that is never dropped loses at most these
+const FLUSH_EVERY: usize = 64;
+
+/// Where ZSE writes its records.
+pub trait LogSink {
+    fn record(&mut self, record: &LogRecord);
+
+    /// Called at every market day, buffered records must be on disk afterwards.
+    /// Sinks also flush on their own after failures, halts and notes, and every `FLUSH_EVERY` records.
+    fn flush(&mut self) {}
+}
+
+#[derive(Clone, Debug)]
+pub enum LogFormat {
+    /// the historical `ZSE|timestamp|OP-...` lines, as `ZSE#instance|timestamp|OP-...`
+    Text,
+    /// one JSON object per line
+    JsonLines,
+    /// nothing is written and no file is opened, for headless runs
+    Off,
+}
+
+/// Writes the historical `ZSE|timestamp|OP-...` format, failures carry the error variant as `-ERROR:Variant`.
+/// The prefix carries the instance, `ZSE#3|...`, since several markets of a process may share the file.
+pub struct TextSink {
+    instance: u32,
+    writer: LogWriter,
+}
+
+/// Writes one JSON object per line, `instance` tells apart markets sharing the file.
+pub struct JsonLinesSink {
+    instance: u32,
+    writer: LogWriter,
+}
+
+struct LogWriter {
+    file: Option<BufWriter<File>>,
+    //records written since the last flush
+    pending: usize,
+}
+
+/// Drops every record.
+pub struct NullSink;
+
+impl LogFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::JsonLines),
+            "off" => Some(LogFormat::Off),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogFormat::Text => "text",
+            LogFormat::JsonLines => "json",
+            LogFormat::Off => "off",
+        }
+    }
+
+    /// `truncate` starts a new log, otherwise records are appended to the existing one.
+    /// A path is truncated once per process, markets sharing it append to each other's records.
+    pub fn open(&self, path: &str, truncate: bool) -> Box<dyn LogSink> {
+        match self {
+            LogFormat::Text => Box::new(TextSink { instance: next_instance(), writer: LogWriter::open(path, truncate) }),
+            LogFormat::JsonLines => Box::new(JsonLinesSink { instance: next_instance(), writer: LogWriter::open(path, truncate) }),
+            LogFormat::Off => Box::new(NullSink),
+        }
+    }
+}
+
+impl Op {
+    /// The `op` field of JSON records.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Init(_) => "init",
+            Op::LockBuy => "lock_buy",
+            Op::Buy => "buy",
+            Op::LockSell => "lock_sell",
+            Op::Sell => "sell",
+            Op::Cancel => "cancel",
+            Op::Expire => "expire",
+            Op::PartialBuy => "partial_buy",
+            Op::PartialSell => "partial_sell",
+            Op::Rebalance(_) => "rebalance",
+            Op::Halt { .. } => "halt",
+            Op::Resume => "resume",
+            Op::Note(_) => "note",
+        }
+    }
+}
+
+impl LogRecord {
+    pub fn new(op: Op) -> Self {
+        LogRecord {
+            op,
+            trader: None,
+            kind: None,
+            qty: None,
+            price: None,
+            token: None,
+            fee: None,
+            outcome: Outcome::Ok,
+        }
+    }
+
+    pub fn trader(mut self, trader: &str) -> Self {
+        self.trader = Some(trader.to_string());
+        self
+    }
+
+    pub fn kind(mut self, kind: GoodKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn qty(mut self, qty: f32) -> Self {
+        self.qty = Some(qty);
+        self
+    }
+
+    pub fn price(mut self, price: f32) -> Self {
+        self.price = Some(price);
+        self
+    }
+
+    pub fn token(mut self, token: &str) -> Self {
+        self.token = Some(token.to_string());
+        self
+    }
+
+    pub fn fee(mut self, fee: f32) -> Self {
+        self.fee = Some(fee);
+        self
+    }
+
+    pub fn failed<E: Debug>(mut self, error: &E) -> Self {
+        self.outcome = Outcome::Error(variant_name(error));
+        self
+    }
+
+    //records worth having on disk right away, the ones you look for after a crash
+    fn urgent(&self) -> bool {
+        matches!(self.outcome, Outcome::Error(_)) || matches!(self.op, Op::Halt { .. } | Op::Resume | Op::Note(_))
+    }
+}
+
+impl LogSink for NullSink {
+    fn record(&mut self, _record: &LogRecord) {}
+}
+
+impl LogSink for TextSink {
+    fn record(&mut self, record: &LogRecord) {
+        let kind = record.kind.map(|k| k.to_string()).unwrap_or_default();
+        let trader = record.trader.clone().unwrap_or_default();
+        let qty = record.qty.unwrap_or_default();
+        let price = record.price.unwrap_or_default();
+        let token = record.token.clone().unwrap_or_default();
+        //`TOKEN-FEE:x`, only on executions that charged something so fee-free logs look like they always did
+        let token = match record.fee {
+            Some(fee) if fee > 0.0 && matches!(record.outcome, Outcome::Ok) => format!("{}-FEE:{}", token, fee),
+            _ => token,
+        };
+        let (ok, error) = match &record.outcome {
+            Outcome::Ok => (true, String::new()),
+            Outcome::Error(variant) => (false, format!("ERROR:{}", variant)),
+        };
+
+        let buffer = match &record.op {
+            Op::Init(q) => format!(
+                "MARKET INITIALIZATION \n EUR: {:+e} \n USD: {:+e} \n YEN: {:+e} \n YUAN: {:+e} \n END MARKET INITIALIZATION",
+                q[0], q[1], q[2], q[3]
+            ),
+            Op::LockBuy if ok => format!("LOCK_BUY-{}-KIND_TO_BUY:{}-QUANTITY_TO_BUY:{}-BID:{}-TOKEN:{}", trader, kind, qty, price, token),
+            Op::LockBuy => format!("LOCK_BUY-{}-KIND_TO_BUY:{}-QUANTITY_TO_BUY:{}-BID:{}-{}", trader, kind, qty, price, error),
+            Op::LockSell if ok => format!("LOCK_SELL-{}-KIND_TO_SELL:{}-QUANTITY_TO_SELL:{}-OFFER:{}-TOKEN:{}", trader, kind, qty, price, token),
+            Op::LockSell => format!("LOCK_SELL-{}-KIND_TO_SELL:{}-QUANTITY_TO_SELL:{}-OFFER:{}-{}", trader, kind, qty, price, error),
+            Op::Buy if ok => format!("BUY-TOKEN:{}-OK", token),
+            Op::Buy => format!("BUY-TOKEN:{}-{}", token, error),
+            Op::Sell if ok => format!("SELL-TOKEN:{}-OK", token),
+            Op::Sell => format!("SELL-TOKEN:{}-{}", token, error),
+            Op::Cancel if ok => format!("CANCEL-TOKEN:{}-OK", token),
+            Op::Cancel => format!("CANCEL-TOKEN:{}-{}", token, error),
+            Op::PartialBuy if ok => format!("PARTIAL_BUY-TOKEN:{}-QUANTITY:{}-PRICE:{}-OK", token, qty, price),
+            Op::PartialBuy => format!("PARTIAL_BUY-TOKEN:{}-QUANTITY:{}-PRICE:{}-{}", token, qty, price, error),
+            Op::PartialSell if ok => format!("PARTIAL_SELL-TOKEN:{}-QUANTITY:{}-PRICE:{}-OK", token, qty, price),
+            Op::PartialSell => format!("PARTIAL_SELL-TOKEN:{}-QUANTITY:{}-PRICE:{}-{}", token, qty, price, error),
+            Op::Rebalance(r) => format!(
+                "REBALANCE-FROM:{}-SOLD:{}-TO:{}-BOUGHT:{}-COST:{}",
+                r.from, r.sold, r.to, r.bought, r.cost
+            ),
+            Op::Halt { reason, days } => format!("HALT-REASON:{}-DAYS:{}", reason.encode(), days),
+            Op::Resume => "RESUME".to_string(),
+            Op::Expire => format!("EXPIRE-TOKEN:{}-KIND:{}-QUANTITY:{}-PRICE:{}", token, kind, qty, price),
+            Op::Note(text) => text.clone(),
+        };
+
+        let atm = Local::now().format("%Y:%m:%d:%H:%M:%S:%3f");
+        self.writer.write_line(&format!("ZSE#{}|{}|{}", self.instance, atm, buffer), record.urgent());
+    }
+
+    fn flush(&mut self) {
+        self.writer.flush();
+    }
+}
+
+impl LogSink for JsonLinesSink {
+    fn record(&mut self, record: &LogRecord) {
+        let mut fields = vec![
+            format!("\"ts\":{}", json_string(&Local::now().to_rfc3339())),
+            "\"market\":\"ZSE\"".to_string(),
+            format!("\"instance\":{}", self.instance),
+        ];
+
+        fields.push(format!("\"op\":\"{}\"", record.op.name()));
+        match &record.op {
+            Op::Init(q) => fields.push(format!(
+                "\"goods\":{{\"EUR\":{},\"USD\":{},\"YEN\":{},\"YUAN\":{}}}",
+                json_number(q[0]), json_number(q[1]), json_number(q[2]), json_number(q[3])
+            )),
+            Op::Rebalance(r) => {
+                fields.push(format!("\"from\":{}", json_string(&r.from.to_string())));
+                fields.push(format!("\"sold\":{}", json_number(r.sold)));
+                fields.push(format!("\"to\":{}", json_string(&r.to.to_string())));
+                fields.push(format!("\"bought\":{}", json_number(r.bought)));
+                fields.push(format!("\"cost\":{}", json_number(r.cost)));
+            }
+            Op::Halt { reason, days } => {
+                fields.push(format!("\"reason\":{}", json_string(&reason.encode())));
+                fields.push(format!("\"days\":{}", days));
+            }
+            Op::Note(text) => fields.push(format!("\"text\":{}", json_string(text))),
+            _ => {}
+        }
+
+        if let Some(trader) = &record.trader {
+            fields.push(format!("\"trader\":{}", json_string(trader)));
+        }
+        if let Some(kind) = record.kind {
+            fields.push(format!("\"kind\":{}", json_string(&kind.to_string())));
+        }
+        if let Some(qty) = record.qty {
+            fields.push(format!("\"qty\":{}", json_number(qty)));
+        }
+        if let Some(price) = record.price {
+            fields.push(format!("\"price\":{}", json_number(price)));
+        }
+        if let Some(token) = &record.token {
+            fields.push(format!("\"token\":{}", json_string(token)));
+        }
+        if let Some(fee) = record.fee {
+            fields.push(format!("\"fee\":{}", json_number(fee)));
+        }
+        match &record.outcome {
+            Outcome::Ok => fields.push("\"outcome\":\"ok\"".to_string()),
+            Outcome::Error(variant) => {
+                fields.push("\"outcome\":\"error\"".to_string());
+                fields.push(format!("\"error\":{}", json_string(variant)));
+            }
+        }
+
+        self.writer.write_line(&format!("{{{}}}", fields.join(",")), record.urgent());
+    }
+
+    fn flush(&mut self) {
+        self.writer.flush();
+    }
+}
+
+/// `BidTooLow { .. }` -> `BidTooLow`
+pub fn variant_name<E: Debug>(error: &E) -> String {
+    let debug = format!("{:?}", error);
+    debug
+        .split(|c: char| c == ' ' || c == '{' || c == '(')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn next_instance() -> u32 {
+    NEXT_INSTANCE.fetch_add(1, Ordering::Relaxed)
+}
+
+impl LogWriter {
+    //always in append mode, so markets sharing a file never write over each other
+    fn open(path: &str, truncate: bool) -> Self {
+        let res = first_open(path, truncate).and_then(|_| OpenOptions::new().create(true).append(true).open(path));
+        let file = match res {
+            Ok(file) => Some(BufWriter::new(file)),
+            Err(e) => {
+                eprintln!("ZSE: cannot open log file {}: {}, logging disabled", path, e);
+                None
+            }
+        };
+        LogWriter { file, pending: 0 }
+    }
+
+    //one write_all per record: a flush only ever carries whole lines, the BufWriter flushes itself on drop
+    fn write_line(&mut self, line: &str, urgent: bool) {
+        if let Some(w) = &mut self.file {
+            if w.write_all(format!("{}\n", line).as_bytes()).is_err() {
+                eprintln!("ZSE: error writing to log file");
+            }
+            self.pending += 1;
+        }
+        if urgent || self.pending >= FLUSH_EVERY {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(w) = &mut self.file {
+            if w.flush().is_err() {
+                eprintln!("ZSE: error writing to log file");
+            }
+        }
+        self.pending = 0;
+    }
+}
+
+fn first_open(path: &str, truncate: bool) -> std::io::Result<()> {
+    if !truncate {
+        return Ok(());
+    }
+    let mut truncated = TRUNCATED.lock().unwrap_or_else(|e| e.into_inner());
+    if truncated.iter().any(|p| p == path) {
+        return Ok(());
+    }
+    File::create(path)?;
+    truncated.push(path.to_string());
+    Ok(())
+}
+
+fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+fn json_number(v: f32) -> String {
+    if v.is_finite() {
+        v.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unitn_market_2022::good::good_kind::GoodKind;
+    use unitn_market_2022::market::LockBuyError;
+
+    use super::{LogFormat, LogRecord, Op};
+    use crate::replay::parse_log;
+
+    #[test]
+    fn shared_file_tells_instances_apart_and_failures_reach_disk() {
+        let path = std::env::temp_dir().join("zse_log_shared_instances.txt");
+        let path = path.to_str().unwrap();
+        let mut first = LogFormat::Text.open(path, true);
+        let mut second = LogFormat::Text.open(path, true);
+
+        let failed = LogRecord::new(Op::LockBuy).trader("t").kind(GoodKind::USD).qty(1.0).price(1.0).failed(&LockBuyError::BidTooLow {
+            requested_good_kind: GoodKind::USD,
+            requested_good_quantity: 1.0,
+            low_bid: 1.0,
+            lowest_acceptable_bid: 2.0,
+        });
+        first.record(&failed);
+        second.record(&failed);
+
+        //both sinks are still alive and never flushed by hand
+        let entries = parse_log(std::fs::read_to_string(path).unwrap().as_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].market.is_some() && entries[1].market.is_some());
+        assert_ne!(entries[0].market, entries[1].market);
+    }
+}
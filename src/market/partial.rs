@@ -0,0 +1,255 @@
+use unitn_market_2022::event::event::{Event, EventKind};
+use unitn_market_2022::good::good::Good;
+use unitn_market_2022::good::good_kind::GoodKind;
+use unitn_market_2022::market::{BuyError, Market, SellError};
+
+use super::log::{LogRecord, Op};
+use super::{buy_token_error, sell_token_error, Mode, ZSE};
+
+/// What a partial execution actually moved.
+#[derive(Debug)]
+pub struct Fill {
+    /// what the trader gets back: the good for a buy, EUR for a sell
+    pub good: Good,
+    /// quantity of the locked good that changed hands
+    pub quantity: f32,
+    /// EUR that changed hands
+    pub price: f32,
+    /// quantity still locked under the same token, 0 once the contract is done
+    pub remaining: f32,
+}
+
+/// Opt-in execution of part of a lock, at the locked unit price.
+/// Fills as much as the supplied cash (buy) or good (sell) covers and keeps the rest of the lock open.
+/// Implemented by ZSE, traders reach it through `MarketRegistry::buy_or_partial`/`sell_or_partial`.
+pub trait PartialFill {
+    fn buy_partial(&mut self, token: String, cash: &mut Good) -> Result<Fill, BuyError>;
+    fn sell_partial(&mut self, token: String, good: &mut Good) -> Result<Fill, SellError>;
+}
+
+impl PartialFill for ZSE {
+    fn buy_partial(&mut self, token: String, cash: &mut Good) -> Result<Fill, BuyError> {
+        let (index, pos) = match self.token.live(&token, Mode::Buy) {
+            Ok(found) => found,
+            Err(state) => return self.reject(LogRecord::new(Op::PartialBuy).token(&token), buy_token_error(token, state)),
+        };
+        let agreed_quantity = self.lock_buy[index].lock[pos].quantity;
+        let agreed_price = self.lock_buy[index].lock[pos].price;
+        let gk = self.goods[index].get_kind();
+
+        if cash.get_kind() != GoodKind::EUR {
+            let record = LogRecord::new(Op::PartialBuy).token(&token).kind(gk);
+            return self.reject(record, BuyError::GoodKindNotDefault { non_default_good_kind: cash.get_kind() });
+        }
+        //enough cash for everything, this is a plain buy
+        if cash.get_qty() >= agreed_price {
+            let good = self.buy(token, cash)?;
+            return Ok(Fill { good, quantity: agreed_quantity, price: agreed_price, remaining: 0.0 });
+        }
+
+        let price = cash.get_qty();
+        let quantity = agreed_quantity * price / agreed_price;
+        let record = LogRecord::new(Op::PartialBuy).token(&token).kind(gk).qty(quantity).price(price);
+        if price <= 0.0 {
+            return self.reject(record, BuyError::InsufficientGoodQuantity { contained_quantity: price, pre_agreed_quantity: agreed_price });
+        }
+
+        self.publish(Event { kind: EventKind::Bought, quantity, price, good_kind: gk });
+
+        //the event itself may have been the tick that expired the lock
+        if let Err(state) = self.token.live(&token, Mode::Buy) {
+            return self.reject(record, buy_token_error(token, state));
+        }
+
+        //float drift can leave us a hair short of what the lock reserved, nothing has moved yet
+        let good = match self.goods[index].split(quantity) {
+            Ok(good) => good,
+            Err(_) => {
+                let held = self.goods[index].get_qty();
+                return self.reject(record, BuyError::InsufficientGoodQuantity { contained_quantity: held, pre_agreed_quantity: quantity });
+            }
+        };
+        let paid = match cash.split(price) {
+            Ok(paid) => paid,
+            Err(_) => {
+                let _ = self.goods[index].merge(good);
+                return self.reject(record, BuyError::InsufficientGoodQuantity { contained_quantity: cash.get_qty(), pre_agreed_quantity: price });
+            }
+        };
+
+        //the fee is charged in proportion to the part filled
+        let fee = self.lock_buy[index].lock[pos].fee * price / agreed_price;
+        let _ = self.goods[0].merge(paid);
+        self.collect_fee(&token, price - fee, fee);
+        self.rates.observe_trade(gk, quantity, price - fee);
+        self.locked_qty[index] -= quantity;
+
+        let contract = &mut self.lock_buy[index].lock[pos];
+        contract.quantity -= quantity;
+        contract.price -= price;
+        contract.fee -= fee;
+        let remaining = contract.quantity;
+
+        self.stats.bought(&record.op, index, quantity, fee);
+        self.record(&record.fee(fee));
+
+        Ok(Fill { good, quantity, price, remaining })
+    }
+
+    fn sell_partial(&mut self, token: String, good: &mut Good) -> Result<Fill, SellError> {
+        let (index, pos) = match self.token.live(&token, Mode::Sell) {
+            Ok(found) => found,
+            Err(state) => return self.reject(LogRecord::new(Op::PartialSell).token(&token), sell_token_error(token, state)),
+        };
+        let agreed_quantity = self.lock_sell[index].lock[pos].quantity;
+        let agreed_price = self.lock_sell[index].lock[pos].price;
+        let gk = self.goods[index].get_kind();
+
+        if good.get_kind() != gk {
+            let record = LogRecord::new(Op::PartialSell).token(&token).kind(gk);
+            return self.reject(record, SellError::WrongGoodKind { wrong_good_kind: good.get_kind(), pre_agreed_kind: gk });
+        }
+        if good.get_qty() >= agreed_quantity {
+            let paid = self.sell(token, good)?;
+            return Ok(Fill { good: paid, quantity: agreed_quantity, price: agreed_price, remaining: 0.0 });
+        }
+
+        let quantity = good.get_qty();
+        let price = agreed_price * quantity / agreed_quantity;
+        let record = LogRecord::new(Op::PartialSell).token(&token).kind(gk).qty(quantity).price(price);
+        if quantity <= 0.0 {
+            return self.reject(record, SellError::InsufficientGoodQuantity { contained_quantity: quantity, pre_agreed_quantity: agreed_quantity });
+        }
+
+        self.publish(Event { kind: EventKind::Sold, quantity, price, good_kind: gk });
+
+        if let Err(state) = self.token.live(&token, Mode::Sell) {
+            return self.reject(record, sell_token_error(token, state));
+        }
+
+        //our EUR first, a split that fails on float drift must not leave the trader's good with us
+        let paid = match self.goods[0].split(price) {
+            Ok(paid) => paid,
+            Err(_) => {
+                let held = self.goods[0].get_qty();
+                return self.reject(record, SellError::InsufficientGoodQuantity { contained_quantity: held, pre_agreed_quantity: price });
+            }
+        };
+        let delivered = match good.split(quantity) {
+            Ok(delivered) => delivered,
+            Err(_) => {
+                let _ = self.goods[0].merge(paid);
+                return self.reject(record, SellError::InsufficientGoodQuantity { contained_quantity: good.get_qty(), pre_agreed_quantity: quantity });
+            }
+        };
+
+        let fee = self.lock_sell[index].lock[pos].fee * quantity / agreed_quantity;
+        let _ = self.goods[index].merge(delivered);
+        self.collect_fee(&token, price + fee, fee);
+        self.rates.observe_trade(gk, quantity, price + fee);
+        self.locked_qty[0] -= price + fee;
+
+        let contract = &mut self.lock_sell[index].lock[pos];
+        contract.quantity -= quantity;
+        contract.price -= price;
+        contract.fee -= fee;
+        let remaining = contract.quantity;
+
+        self.stats.sold(&record.op, index, quantity, fee);
+        self.record(&record.fee(fee));
+
+        Ok(Fill { good: paid, quantity, price, remaining })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    use unitn_market_2022::event::event::Event;
+    use unitn_market_2022::event::notifiable::Notifiable;
+    use unitn_market_2022::good::good::Good;
+    use unitn_market_2022::good::good_kind::GoodKind;
+    use unitn_market_2022::market::{BuyError, Market, SellError};
+
+    use super::super::{FeeSchedule, LogFormat, TreasuryTargets, ZseConfig, ZSE};
+    use super::PartialFill;
+
+    //counts what the market broadcasts
+    struct Counter(Rc<Cell<u32>>);
+
+    impl Notifiable for Counter {
+        fn add_subscriber(&mut self, _subscriber: Box<dyn Notifiable>) {}
+
+        fn on_event(&mut self, _event: Event) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    fn market() -> (Rc<RefCell<ZSE>>, Rc<Cell<u32>>) {
+        //no rebalancing, so our goods only move with the fills
+        let treasury = TreasuryTargets { weights: [0.0; 4], ..Default::default() };
+        let config = ZseConfig::builder().fees(FeeSchedule::Flat(10.0)).treasury(treasury).log_format(LogFormat::Off).build();
+        let market = ZSE::with_config(10000.0, 10000.0, 10000.0, 10000.0, config, 0);
+        let events = Rc::new(Cell::new(0));
+        market.borrow_mut().add_subscriber(Box::new(Counter(events.clone())));
+        (market, events)
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn buy_fills_in_proportion_and_keeps_the_rest_locked() {
+        let (market, events) = market();
+        let mut m = market.borrow_mut();
+        let bid = m.get_buy_price(GoodKind::USD, 100.0).unwrap();
+        let token = m.lock_buy(GoodKind::USD, 100.0, bid, "tester".to_string()).unwrap();
+
+        //what the registry does: the full buy first, the partial once it is refused for the cash
+        let mut cash = Good::new(GoodKind::EUR, bid / 4.0);
+        events.set(0);
+        assert!(matches!(m.buy(token.clone(), &mut cash), Err(BuyError::InsufficientGoodQuantity { .. })));
+        let fill = m.buy_partial(token.clone(), &mut cash).unwrap();
+        assert_eq!(events.get(), 1);
+
+        assert!(close(fill.quantity, 25.0) && close(fill.good.get_qty(), 25.0));
+        assert!(close(fill.price, bid / 4.0) && close(cash.get_qty(), 0.0));
+        assert!(close(fill.remaining, 75.0) && close(m.locked_qty[1], 75.0));
+        assert!(close(m.fee_account, 2.5));
+        let contract = m.lock_buy[1].lock.iter().find(|c| c.token == token).unwrap();
+        assert!(close(contract.price, bid * 0.75) && close(contract.fee, 7.5));
+
+        //enough for the rest is a plain buy of what is left
+        let fill = m.buy_partial(token.clone(), &mut Good::new(GoodKind::EUR, bid)).unwrap();
+        assert_eq!(events.get(), 2);
+        assert!(close(fill.quantity, 75.0) && fill.remaining == 0.0);
+        assert!(close(m.locked_qty[1], 0.0) && close(m.fee_account, 10.0));
+        assert_eq!(m.lock_buy[1].last, 0);
+    }
+
+    #[test]
+    fn sell_fills_in_proportion_and_keeps_the_rest_locked() {
+        let (market, events) = market();
+        let mut m = market.borrow_mut();
+        let offer = m.get_sell_price(GoodKind::USD, 100.0).unwrap();
+        let token = m.lock_sell(GoodKind::USD, 100.0, offer, "tester".to_string()).unwrap();
+        let eur = m.goods[0].get_qty();
+
+        let mut usd = Good::new(GoodKind::USD, 40.0);
+        events.set(0);
+        assert!(matches!(m.sell(token.clone(), &mut usd), Err(SellError::InsufficientGoodQuantity { .. })));
+        let fill = m.sell_partial(token.clone(), &mut usd).unwrap();
+        assert_eq!(events.get(), 1);
+
+        assert!(close(fill.quantity, 40.0) && close(usd.get_qty(), 0.0));
+        assert!(close(fill.price, offer * 0.4) && close(fill.good.get_qty(), offer * 0.4));
+        assert!(close(fill.remaining, 60.0));
+        //the fee comes out of our EUR on top of the price, 40% of it so far
+        assert!(close(m.fee_account, 4.0));
+        assert!(close(m.goods[0].get_qty(), eur - offer * 0.4 - 4.0));
+        assert!(close(m.locked_qty[0], (offer + 10.0) * 0.6));
+    }
+}
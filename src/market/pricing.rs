@@ -0,0 +1,193 @@
+use unitn_market_2022::event::event::{Event, EventKind};
+use unitn_market_2022::good::consts::{DEFAULT_EUR_USD_EXCHANGE_RATE, DEFAULT_EUR_YEN_EXCHANGE_RATE, DEFAULT_EUR_YUAN_EXCHANGE_RATE};
+use unitn_market_2022::good::good::Good;
+use unitn_market_2022::good::good_kind::GoodKind;
+
+/// Decides how ZSE moves its prices.
+/// Called on every event the market sees, `external` is true when the event comes from a subscribed market.
+/// Returns the new `(prices_buy, prices_sell)`.
+pub trait PricingStrategy {
+    fn reprice(&mut self, event: &Event, external: bool, goods: &[Good; 4], prices_buy: &[f32; 4], prices_sell: &[f32; 4]) -> ([f32; 4], [f32; 4]);
+}
+
+/// Strategies that can be picked by name, e.g. from a config file.
+#[derive(Clone, Debug)]
+pub enum PricingKind {
+    Dumping,
+    InventorySkew,
+    FixedSpread,
+}
+
+impl PricingKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dumping" => Some(PricingKind::Dumping),
+            "inventory_skew" => Some(PricingKind::InventorySkew),
+            "fixed_spread" => Some(PricingKind::FixedSpread),
+            _ => None,
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn PricingStrategy> {
+        match self {
+            PricingKind::Dumping => Box::new(DumpingStrategy::default()),
+            PricingKind::InventorySkew => Box::new(InventorySkewStrategy::default()),
+            PricingKind::FixedSpread => Box::new(FixedSpreadStrategy::default()),
+        }
+    }
+}
+
+/// The original ZSE behaviour: pull back toward the default rate after our own trades,
+/// undercut competitors whenever they trade at a better price.
+pub struct DumpingStrategy {
+    pub pull: f32,
+    pub undercut: f32,
+    pub lock_undercut: f32,
+}
+
+/// Quotes around the default rate and skews both sides by how far each good is from an equal EUR-valued share.
+/// Goods the market is short of get more expensive to buy and pay more when sold to us.
+pub struct InventorySkewStrategy {
+    pub half_spread: f32,
+    pub sensitivity: f32,
+}
+
+/// Constant spread around the default rate, events are ignored.
+pub struct FixedSpreadStrategy {
+    pub spread: f32,
+}
+
+impl Default for DumpingStrategy {
+    fn default() -> Self {
+        DumpingStrategy {
+            pull: 0.8,
+            undercut: 0.015,
+            lock_undercut: 0.01,
+        }
+    }
+}
+
+impl Default for InventorySkewStrategy {
+    fn default() -> Self {
+        InventorySkewStrategy {
+            half_spread: 0.005,
+            sensitivity: 0.05,
+        }
+    }
+}
+
+impl Default for FixedSpreadStrategy {
+    fn default() -> Self {
+        FixedSpreadStrategy {
+            spread: 0.01,
+        }
+    }
+}
+
+impl PricingStrategy for DumpingStrategy {
+    fn reprice(&mut self, event: &Event, external: bool, _goods: &[Good; 4], prices_buy: &[f32; 4], prices_sell: &[f32; 4]) -> ([f32; 4], [f32; 4]) {
+        let mut buy = *prices_buy;
+        let mut sell = *prices_sell;
+        if event.quantity <= 0.0 {
+            return (buy, sell);
+        }
+
+        let unit_price = event.price / event.quantity;
+        let index = index_of(&event.good_kind);
+        let exchange = default_rate(&event.good_kind);
+
+        match event.kind {
+            EventKind::Bought => {
+                if !external {
+                    let diff = exchange - buy[index];
+                    buy[index] += diff * self.pull;
+                }
+                if external && unit_price < buy[index] {
+                    buy[index] = unit_price - (unit_price * self.undercut);
+                }
+            },
+            EventKind::Sold => {
+                if !external {
+                    let diff = sell[index] - exchange;
+                    sell[index] -= diff * self.pull;
+                }
+                if external && unit_price > sell[index] {
+                    sell[index] = unit_price + (unit_price * self.undercut);
+                }
+            },
+            EventKind::LockedBuy => {
+                if external && unit_price > sell[index] {
+                    sell[index] = unit_price + (unit_price * self.lock_undercut);
+                }
+            },
+            EventKind::LockedSell => {
+                if external && unit_price < buy[index] {
+                    buy[index] = unit_price - (unit_price * self.lock_undercut);
+                }
+            },
+            _ => {}
+        };
+
+        (buy, sell)
+    }
+}
+
+impl PricingStrategy for InventorySkewStrategy {
+    fn reprice(&mut self, _event: &Event, _external: bool, goods: &[Good; 4], prices_buy: &[f32; 4], prices_sell: &[f32; 4]) -> ([f32; 4], [f32; 4]) {
+        let mut buy = *prices_buy;
+        let mut sell = *prices_sell;
+
+        let values: Vec<f32> = goods.iter().map(|g| g.get_qty() / default_rate(&g.get_kind())).collect();
+        let total: f32 = values.iter().sum();
+        if total <= 0.0 {
+            return (buy, sell);
+        }
+        let target = 1.0 / goods.len() as f32;
+
+        //EUR is the unit of account, its price never moves
+        for g in goods.iter().skip(1) {
+            let index = index_of(&g.get_kind());
+            let exchange = default_rate(&g.get_kind());
+            let share = values[index] / total;
+            let skew = ((target - share) / target).clamp(-1.0, 1.0) * self.sensitivity;
+            buy[index] = exchange * (1.0 + self.half_spread + skew);
+            sell[index] = exchange * (1.0 - self.half_spread + skew);
+        }
+
+        (buy, sell)
+    }
+}
+
+impl PricingStrategy for FixedSpreadStrategy {
+    fn reprice(&mut self, _event: &Event, _external: bool, goods: &[Good; 4], prices_buy: &[f32; 4], prices_sell: &[f32; 4]) -> ([f32; 4], [f32; 4]) {
+        let mut buy = *prices_buy;
+        let mut sell = *prices_sell;
+
+        for g in goods.iter().skip(1) {
+            let index = index_of(&g.get_kind());
+            let exchange = default_rate(&g.get_kind());
+            buy[index] = exchange * (1.0 + self.spread / 2.0);
+            sell[index] = exchange * (1.0 - self.spread / 2.0);
+        }
+
+        (buy, sell)
+    }
+}
+
+fn index_of(kind: &GoodKind) -> usize {
+    match *kind {
+        GoodKind::EUR => 0,
+        GoodKind::USD => 1,
+        GoodKind::YEN => 2,
+        GoodKind::YUAN => 3,
+    }
+}
+
+fn default_rate(kind: &GoodKind) -> f32 {
+    match *kind {
+        GoodKind::EUR => 1.0,
+        GoodKind::USD => DEFAULT_EUR_USD_EXCHANGE_RATE,
+        GoodKind::YEN => DEFAULT_EUR_YEN_EXCHANGE_RATE,
+        GoodKind::YUAN => DEFAULT_EUR_YUAN_EXCHANGE_RATE,
+    }
+}
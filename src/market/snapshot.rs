@@ -0,0 +1,659 @@
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::rc::Rc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use unitn_market_2022::good::good_kind::GoodKind;
+
+use crate::oracle::RateSource;
+
+use super::breaker::{BreakerEvent, CircuitBreakers, HaltReason};
+use super::curve::PriceCurve;
+use super::expiry::ExpiryClock;
+use super::fees::FeeSchedule;
+use super::log::LogFormat;
+use super::pricing::PricingKind;
+use super::stats::StatsBook;
+use super::tokens::TokenEntry;
+use super::traders::TraderLimits;
+use super::treasury::Rebalance;
+use super::{Mode, TokenState, ZseConfig, ZSE};
+
+const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(String),
+    MissingHeader,
+    UnsupportedVersion(u32),
+    Parse { line: usize, text: String },
+    Truncated,
+    /// a line every snapshot has, by tag
+    Missing(&'static str),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "snapshot io error: {}", e),
+            SnapshotError::MissingHeader => write!(f, "not a ZSE snapshot"),
+            SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+            SnapshotError::Parse { line, text } => write!(f, "line {}: cannot parse `{}`", line, text),
+            SnapshotError::Truncated => write!(f, "snapshot ends before END"),
+            SnapshotError::Missing(tag) => write!(f, "snapshot has no {} line", tag),
+        }
+    }
+}
+
+impl ZSE {
+    /// Writes the whole market state to `path`, so a restored market goes on exactly like this one.
+    /// Every line is `TAG values...`, floats use the shortest representation that reads back to the same value.
+    /// Not saved: subscribed markets and breaker subscribers, and a pricing strategy given to `with_strategy`,
+    /// which comes back as the `pricing` of the config.
+    /// The token rng can't be written out, so both markets continue from a seed drawn from it here.
+    pub fn save_snapshot(&mut self, path: &str) -> Result<(), SnapshotError> {
+        let file = File::create(path).map_err(|e| SnapshotError::Io(e.to_string()))?;
+        let mut w = BufWriter::new(file);
+        let seed = self.rng.gen();
+        self.rng = StdRng::seed_from_u64(seed);
+        self.write_snapshot(&mut w, seed).map_err(|e| SnapshotError::Io(e.to_string()))
+    }
+
+    /// Rebuilds a market saved with `save_snapshot`.
+    /// The log file is appended to, not truncated, and subscribers have to be added again.
+    pub fn load_snapshot(path: &str) -> Result<Rc<RefCell<ZSE>>, SnapshotError> {
+        let file = File::open(path).map_err(|e| SnapshotError::Io(e.to_string()))?;
+        let mut market = Self::read_snapshot(BufReader::new(file))?;
+
+        market.note(format!("SNAPSHOT_RESTORED-PATH:{}", path));
+
+        Ok(Rc::new(RefCell::new(market)))
+    }
+
+    fn write_snapshot(&self, w: &mut impl Write, seed: u64) -> std::io::Result<()> {
+        writeln!(w, "ZSE_SNAPSHOT {}", SNAPSHOT_VERSION)?;
+
+        writeln!(w, "CONFIG max_locks {}", self.config.max_locks)?;
+        writeln!(w, "CONFIG lock_expiry {}", self.config.lock_expiry)?;
+        writeln!(w, "CONFIG expiry_clock {}", self.config.expiry_clock.name())?;
+        writeln!(w, "CONFIG target_weights {}", join(self.config.treasury.weights.iter()))?;
+        writeln!(w, "CONFIG rebalance_tolerance {}", self.config.treasury.tolerance)?;
+        writeln!(w, "CONFIG rebalance_cost {}", self.config.treasury.cost)?;
+        writeln!(w, "CONFIG breakers {}", encode_breakers(&self.config.breakers))?;
+        writeln!(w, "CONFIG conversion_cooldown {}", self.config.conversion_cooldown)?;
+        writeln!(w, "CONFIG token_retention {}", self.config.token_retention)?;
+        writeln!(w, "CONFIG trader_limits {}", encode_limits(&self.config.trader_limits))?;
+        for (trader, limits) in self.config.trader_limits_for.iter() {
+            writeln!(w, "CONFIG trader_limits_for {} {}", encode_limits(limits), trader)?;
+        }
+        writeln!(w, "CONFIG fees {}", self.config.fees.encode())?;
+        writeln!(w, "CONFIG rates {}", self.config.rates.name())?;
+        for trader in self.config.makers.iter() {
+            writeln!(w, "CONFIG maker {}", trader)?;
+        }
+        writeln!(w, "CONFIG pricing {}", self.config.pricing.name())?;
+        writeln!(w, "CONFIG curve {}", encode_curve(&self.config.curve))?;
+        writeln!(w, "CONFIG log_format {}", self.config.log_format.name())?;
+        writeln!(w, "CONFIG log_path {}", self.config.log_path)?;
+
+        writeln!(w, "GOODS {}", join(self.goods.iter().map(|g| g.get_qty())))?;
+        writeln!(w, "PRICES_BUY {}", join(self.prices_buy.iter()))?;
+        writeln!(w, "PRICES_SELL {}", join(self.prices_sell.iter()))?;
+        writeln!(w, "LOCKED_QTY {}", join(self.locked_qty.iter()))?;
+        for row in self.conversion_timer.iter() {
+            writeln!(w, "TIMER {}", join(row.iter()))?;
+        }
+
+        for (mode, locks) in [("BUY", &self.lock_buy), ("SELL", &self.lock_sell)] {
+            for (i, lock) in locks.iter().enumerate() {
+                writeln!(w, "LOCK {} {} {}", mode, i, lock.last)?;
+                for (j, c) in lock.lock.iter().enumerate() {
+                    if c.token.is_empty() {
+                        continue;
+                    }
+                    writeln!(w, "CONTRACT {} {} {} {} {} {} {} {}", mode, i, j, c.token, c.quantity, c.price, c.lock_counter, c.fee)?;
+                }
+            }
+        }
+
+        for (trader, notional) in self.traders.iter() {
+            writeln!(w, "NOTIONAL {} {}", notional, trader)?;
+        }
+        for (trader, volume) in self.traders.volumes() {
+            writeln!(w, "VOLUME {} {}", volume, trader)?;
+        }
+        writeln!(w, "CLOCK {}", self.token.now())?;
+        writeln!(w, "RNG {}", seed)?;
+        writeln!(w, "FEE_ACCOUNT {}", self.fee_account)?;
+        let rates = self.rates.state();
+        if !rates.is_empty() {
+            writeln!(w, "RATES {}", join(rates.iter()))?;
+        }
+
+        for (buy, sell) in self.breaker.prices.iter() {
+            writeln!(w, "BREAKER_PRICES {} {}", join(buy.iter()), join(sell.iter()))?;
+        }
+        if let Some(free) = &self.breaker.free {
+            writeln!(w, "BREAKER_FREE {}", join(free.iter()))?;
+        }
+        if let Some((reason, days)) = &self.breaker.halt {
+            writeln!(w, "HALT {} {}", days, reason.encode())?;
+        }
+        for event in self.breaker.events.iter() {
+            match event {
+                BreakerEvent::Halted { reason, days } => writeln!(w, "BREAKER_EVENT halted {} {}", days, reason.encode())?,
+                BreakerEvent::Resumed => writeln!(w, "BREAKER_EVENT resumed")?,
+            }
+        }
+        for r in self.rebalances() {
+            writeln!(w, "REBALANCE {} {} {} {} {}", r.from, r.sold, r.to, r.bought, r.cost)?;
+        }
+
+        let book = &self.stats;
+        let s = &book.stats;
+        writeln!(w, "STATS_BOUGHT {}", join(s.bought.iter()))?;
+        writeln!(w, "STATS_SOLD {}", join(s.sold.iter()))?;
+        writeln!(w, "STATS_SPREAD_SUM {}", join(book.spread_sum.iter()))?;
+        writeln!(w, "STATS {} {} {} {} {}", book.utilisation_sum, s.expired_locks, s.rebalances, s.fee_income, s.events)?;
+        for (op, count) in s.succeeded.iter() {
+            writeln!(w, "STATS_OK {} {}", count, op)?;
+        }
+        for ((op, error), count) in s.failed.iter() {
+            writeln!(w, "STATS_FAILED {} {} {}", count, op, error)?;
+        }
+
+        for (token, e) in self.token.iter() {
+            let mode = match e.mode {
+                Mode::Buy => "BUY",
+                Mode::Sell => "SELL",
+            };
+            //trader goes last, names may contain spaces
+            writeln!(w, "TOKEN {} {} {} {} {} {} {}", token, state_name(e.state), mode, e.index, e.slot, e.created_at, e.trader)?;
+        }
+
+        writeln!(w, "END")?;
+        w.flush()
+    }
+
+    fn read_snapshot(reader: impl BufRead) -> Result<ZSE, SnapshotError> {
+        let mut lines = reader.lines().enumerate();
+
+        match lines.next() {
+            Some((_, Ok(header))) => {
+                let version = header
+                    .strip_prefix("ZSE_SNAPSHOT ")
+                    .ok_or(SnapshotError::MissingHeader)?
+                    .trim()
+                    .parse::<u32>()
+                    .map_err(|_| SnapshotError::MissingHeader)?;
+                if version != SNAPSHOT_VERSION {
+                    return Err(SnapshotError::UnsupportedVersion(version));
+                }
+            }
+            Some((_, Err(e))) => return Err(SnapshotError::Io(e.to_string())),
+            None => return Err(SnapshotError::MissingHeader),
+        }
+
+        let mut config = ZseConfig::default();
+        let mut goods = [0.0; 4];
+        let mut prices_buy = [0.0; 4];
+        let mut prices_sell = [0.0; 4];
+        let mut locked_qty = [0.0; 4];
+        let mut conversion_timer = [[0; 4]; 4];
+        let mut timer_row = 0;
+        let mut lock_last = [[0; 4]; 2];
+        let mut contracts = Vec::new();
+        let mut tokens = Vec::new();
+        let mut clock = 0;
+        let mut seed = None;
+        let mut fee_account = 0.0;
+        let mut rates = Vec::new();
+        let mut breaker_prices: Vec<([f32; 4], [f32; 4])> = Vec::new();
+        let mut breaker_free = None;
+        let mut halt = None;
+        let mut breaker_events = Vec::new();
+        let mut rebalances = Vec::new();
+        let mut stats = StatsBook::default();
+        let mut notional = Vec::new();
+        let mut volume = Vec::new();
+        let mut finished = false;
+
+        for (n, line) in lines {
+            let line = line.map_err(|e| SnapshotError::Io(e.to_string()))?;
+            let bad = || SnapshotError::Parse { line: n + 1, text: line.clone() };
+            let mut fields = line.split(' ');
+            let tag = fields.next().unwrap_or("");
+            let rest: Vec<&str> = fields.collect();
+
+            match tag {
+                "CONFIG" => {
+                    let key = rest.first().ok_or_else(bad)?;
+                    let value = rest[1..].join(" ");
+                    match *key {
+                        "max_locks" => config.max_locks = value.parse().map_err(|_| bad())?,
+                        "lock_expiry" => config.lock_expiry = value.parse().map_err(|_| bad())?,
+                        "expiry_clock" => config.expiry_clock = ExpiryClock::from_name(&value).ok_or_else(bad)?,
+                        "target_weights" => config.treasury.weights = parse_four(&rest[1..]).ok_or_else(bad)?,
+                        "rebalance_tolerance" => config.treasury.tolerance = value.parse().map_err(|_| bad())?,
+                        "rebalance_cost" => config.treasury.cost = value.parse().map_err(|_| bad())?,
+                        "breakers" => config.breakers = decode_breakers(&rest[1..]).ok_or_else(bad)?,
+                        "conversion_cooldown" => config.conversion_cooldown = value.parse().map_err(|_| bad())?,
+                        "token_retention" => config.token_retention = value.parse().map_err(|_| bad())?,
+                        "trader_limits" => config.trader_limits = decode_limits(&rest[1..]).ok_or_else(bad)?,
+                        "trader_limits_for" => {
+                            if rest.len() < 5 {
+                                return Err(bad());
+                            }
+                            let limits = decode_limits(&rest[1..5]).ok_or_else(bad)?;
+                            config.trader_limits_for.insert(rest[5..].join(" "), limits);
+                        }
+                        "fees" => config.fees = FeeSchedule::parse(&value).ok_or_else(bad)?,
+                        "rates" => config.rates = RateSource::from_name(&value).ok_or_else(bad)?,
+                        "maker" => {
+                            config.makers.insert(value);
+                        }
+                        "pricing" => config.pricing = PricingKind::from_name(&value).ok_or_else(bad)?,
+                        "curve" => config.curve = decode_curve(&rest[1..]).ok_or_else(bad)?,
+                        "log_format" => config.log_format = LogFormat::from_name(&value).ok_or_else(bad)?,
+                        "log_path" => config.log_path = value,
+                        _ => return Err(bad()),
+                    }
+                }
+                "GOODS" => goods = parse_four(&rest).ok_or_else(bad)?,
+                "PRICES_BUY" => prices_buy = parse_four(&rest).ok_or_else(bad)?,
+                "PRICES_SELL" => prices_sell = parse_four(&rest).ok_or_else(bad)?,
+                "LOCKED_QTY" => locked_qty = parse_four(&rest).ok_or_else(bad)?,
+                "TIMER" => {
+                    if timer_row >= 4 {
+                        return Err(bad());
+                    }
+                    conversion_timer[timer_row] = parse_four(&rest).ok_or_else(bad)?;
+                    timer_row += 1;
+                }
+                "LOCK" => {
+                    if rest.len() != 3 {
+                        return Err(bad());
+                    }
+                    let mode = mode_index(rest[0]).ok_or_else(bad)?;
+                    let good: usize = rest[1].parse().map_err(|_| bad())?;
+                    if good >= 4 {
+                        return Err(bad());
+                    }
+                    lock_last[mode][good] = rest[2].parse().map_err(|_| bad())?;
+                }
+                "CONTRACT" => {
+                    if rest.len() != 8 {
+                        return Err(bad());
+                    }
+                    let mode = mode_index(rest[0]).ok_or_else(bad)?;
+                    let good: usize = rest[1].parse().map_err(|_| bad())?;
+                    let slot: usize = rest[2].parse().map_err(|_| bad())?;
+                    let quantity: f32 = rest[4].parse().map_err(|_| bad())?;
+                    let price: f32 = rest[5].parse().map_err(|_| bad())?;
+                    let counter: i32 = rest[6].parse().map_err(|_| bad())?;
+                    let fee: f32 = rest[7].parse().map_err(|_| bad())?;
+                    if good >= 4 {
+                        return Err(bad());
+                    }
+                    contracts.push((mode, good, slot, rest[3].to_string(), quantity, price, counter, fee, n + 1, line.clone()));
+                }
+                "NOTIONAL" => {
+                    let amount: f32 = rest.first().ok_or_else(bad)?.parse().map_err(|_| bad())?;
+                    notional.push((rest[1..].join(" "), amount));
+                }
+                "VOLUME" => {
+                    let amount: f32 = rest.first().ok_or_else(bad)?.parse().map_err(|_| bad())?;
+                    volume.push((rest[1..].join(" "), amount));
+                }
+                "FEE_ACCOUNT" => fee_account = rest.first().ok_or_else(bad)?.parse().map_err(|_| bad())?,
+                "RATES" => rates = rest.iter().map(|r| r.parse()).collect::<Result<Vec<f32>, _>>().map_err(|_| bad())?,
+                "BREAKER_PRICES" => {
+                    if rest.len() != 8 {
+                        return Err(bad());
+                    }
+                    breaker_prices.push((parse_four(&rest[..4]).ok_or_else(bad)?, parse_four(&rest[4..]).ok_or_else(bad)?))
+                }
+                "BREAKER_FREE" => breaker_free = Some(parse_four(&rest).ok_or_else(bad)?),
+                "HALT" => {
+                    let days: u32 = rest.first().ok_or_else(bad)?.parse().map_err(|_| bad())?;
+                    halt = Some((HaltReason::parse(&rest[1..].join(" ")).ok_or_else(bad)?, days));
+                }
+                "BREAKER_EVENT" => match rest.first() {
+                    Some(&"resumed") if rest.len() == 1 => breaker_events.push(BreakerEvent::Resumed),
+                    Some(&"halted") if rest.len() > 1 => {
+                        let days: u32 = rest[1].parse().map_err(|_| bad())?;
+                        let reason = HaltReason::parse(&rest[2..].join(" ")).ok_or_else(bad)?;
+                        breaker_events.push(BreakerEvent::Halted { reason, days });
+                    }
+                    _ => return Err(bad()),
+                },
+                "REBALANCE" => {
+                    if rest.len() != 5 {
+                        return Err(bad());
+                    }
+                    rebalances.push(Rebalance {
+                        from: parse_kind(rest[0]).ok_or_else(bad)?,
+                        sold: rest[1].parse().map_err(|_| bad())?,
+                        to: parse_kind(rest[2]).ok_or_else(bad)?,
+                        bought: rest[3].parse().map_err(|_| bad())?,
+                        cost: rest[4].parse().map_err(|_| bad())?,
+                    });
+                }
+                "STATS_BOUGHT" => stats.stats.bought = parse_four(&rest).ok_or_else(bad)?,
+                "STATS_SOLD" => stats.stats.sold = parse_four(&rest).ok_or_else(bad)?,
+                "STATS_SPREAD_SUM" => stats.spread_sum = parse_four(&rest).ok_or_else(bad)?,
+                "STATS" => {
+                    if rest.len() != 5 {
+                        return Err(bad());
+                    }
+                    stats.utilisation_sum = rest[0].parse().map_err(|_| bad())?;
+                    stats.stats.expired_locks = rest[1].parse().map_err(|_| bad())?;
+                    stats.stats.rebalances = rest[2].parse().map_err(|_| bad())?;
+                    stats.stats.fee_income = rest[3].parse().map_err(|_| bad())?;
+                    stats.stats.events = rest[4].parse().map_err(|_| bad())?;
+                }
+                "STATS_OK" => {
+                    if rest.len() != 2 {
+                        return Err(bad());
+                    }
+                    let count: u32 = rest[0].parse().map_err(|_| bad())?;
+                    stats.stats.succeeded.insert(op_name(rest[1]).ok_or_else(bad)?, count);
+                }
+                "STATS_FAILED" => {
+                    if rest.len() != 3 {
+                        return Err(bad());
+                    }
+                    let count: u32 = rest[0].parse().map_err(|_| bad())?;
+                    stats.stats.failed.insert((op_name(rest[1]).ok_or_else(bad)?, rest[2].to_string()), count);
+                }
+                "CLOCK" => clock = rest.first().ok_or_else(bad)?.parse().map_err(|_| bad())?,
+                "RNG" => seed = Some(rest.first().ok_or_else(bad)?.parse().map_err(|_| bad())?),
+                "TOKEN" => {
+                    if rest.len() < 6 {
+                        return Err(bad());
+                    }
+                    let state = parse_state(rest[1]).ok_or_else(bad)?;
+                    let mode = if mode_index(rest[2]).ok_or_else(bad)? == 0 { Mode::Buy } else { Mode::Sell };
+                    let good: usize = rest[3].parse().map_err(|_| bad())?;
+                    let slot: usize = rest[4].parse().map_err(|_| bad())?;
+                    let created_at: u64 = rest[5].parse().map_err(|_| bad())?;
+                    if good >= 4 {
+                        return Err(bad());
+                    }
+                    tokens.push((rest[0].to_string(), TokenEntry { index: good, slot, mode, state, trader: rest[6..].join(" "), created_at }));
+                }
+                "END" => {
+                    finished = true;
+                    break;
+                }
+                "" => {}
+                _ => return Err(bad()),
+            }
+        }
+        if !finished {
+            return Err(SnapshotError::Truncated);
+        }
+        let seed = seed.ok_or(SnapshotError::Missing("RNG"))?;
+
+        let pricing = config.pricing.build();
+        let mut market = ZSE::build(goods[0], goods[1], goods[2], goods[3], pricing, config, false, StdRng::seed_from_u64(seed));
+        market.prices_buy = prices_buy;
+        market.prices_sell = prices_sell;
+        market.locked_qty = locked_qty;
+        market.conversion_timer = conversion_timer;
+
+        for i in 0..4 {
+            market.lock_buy[i].last = lock_last[0][i];
+            market.lock_sell[i].last = lock_last[1][i];
+        }
+        for (token, entry) in tokens {
+            market.token.restore(token, entry);
+        }
+        market.token.restore_clock(clock);
+        for (trader, amount) in notional {
+            market.traders.add_notional(&trader, amount);
+        }
+        for (trader, amount) in volume {
+            market.traders.add_volume(&trader, amount);
+        }
+
+        market.fee_account = fee_account;
+        market.rates.restore(&rates);
+        market.breaker.prices = breaker_prices.into();
+        market.breaker.free = breaker_free;
+        market.breaker.halt = halt;
+        market.breaker.events = breaker_events.into();
+        for rebalance in rebalances {
+            market.rebalances.push(rebalance);
+        }
+        market.stats = stats;
+
+        for (mode, good, slot, token, quantity, price, counter, fee, line, text) in contracts {
+            let locks = if mode == 0 { &mut market.lock_buy } else { &mut market.lock_sell };
+            let contract = locks[good].lock.get_mut(slot).ok_or(SnapshotError::Parse { line, text })?;
+            contract.token = token;
+            contract.quantity = quantity;
+            contract.price = price;
+            contract.lock_counter = counter;
+            contract.fee = fee;
+        }
+
+        Ok(market)
+    }
+}
+
+impl PricingKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PricingKind::Dumping => "dumping",
+            PricingKind::InventorySkew => "inventory_skew",
+            PricingKind::FixedSpread => "fixed_spread",
+        }
+    }
+}
+
+fn encode_curve(curve: &PriceCurve) -> String {
+    match curve {
+        PriceCurve::Linear => "linear".to_string(),
+        PriceCurve::ConstantProduct => "constant_product".to_string(),
+        PriceCurve::Tiered(tiers) => {
+            let tiers: Vec<String> = tiers.iter().map(|(share, spread)| format!("{}:{}", share, spread)).collect();
+            format!("tiered {}", tiers.join(" "))
+        }
+    }
+}
+
+fn decode_curve(fields: &[&str]) -> Option<PriceCurve> {
+    match *fields.first()? {
+        "linear" => Some(PriceCurve::Linear),
+        "constant_product" => Some(PriceCurve::ConstantProduct),
+        "tiered" => {
+            let mut tiers = Vec::new();
+            for f in &fields[1..] {
+                let (share, spread) = f.split_once(':')?;
+                tiers.push((share.parse().ok()?, spread.parse().ok()?));
+            }
+            Some(PriceCurve::Tiered(tiers))
+        }
+        _ => None,
+    }
+}
+
+//max_open_locks max_locks_per_good max_daily_notional max_inventory_share, `-` for no limit
+fn encode_limits(limits: &TraderLimits) -> String {
+    fn field<T: ToString>(v: Option<T>) -> String {
+        v.map_or("-".to_string(), |v| v.to_string())
+    }
+    format!(
+        "{} {} {} {}",
+        field(limits.max_open_locks),
+        field(limits.max_locks_per_good),
+        field(limits.max_daily_notional),
+        field(limits.max_inventory_share)
+    )
+}
+
+//max_move window halt_days floor floor floor floor, `-` for none
+fn encode_breakers(breakers: &CircuitBreakers) -> String {
+    fn field<T: ToString>(v: Option<T>) -> String {
+        v.map_or("-".to_string(), |v| v.to_string())
+    }
+    let floors: Vec<String> = breakers.inventory_floor.iter().map(|f| field(*f)).collect();
+    format!("{} {} {} {}", field(breakers.max_move), breakers.window, breakers.halt_days, floors.join(" "))
+}
+
+fn decode_breakers(fields: &[&str]) -> Option<CircuitBreakers> {
+    fn field(v: &str) -> Option<Option<f32>> {
+        match v {
+            "-" => Some(None),
+            v => v.parse().ok().map(Some),
+        }
+    }
+    if fields.len() != 7 {
+        return None;
+    }
+    Some(CircuitBreakers {
+        max_move: field(fields[0])?,
+        window: fields[1].parse().ok()?,
+        halt_days: fields[2].parse().ok()?,
+        inventory_floor: [field(fields[3])?, field(fields[4])?, field(fields[5])?, field(fields[6])?],
+    })
+}
+
+fn decode_limits(fields: &[&str]) -> Option<TraderLimits> {
+    fn field<T: std::str::FromStr>(v: &str) -> Option<Option<T>> {
+        match v {
+            "-" => Some(None),
+            v => v.parse().ok().map(Some),
+        }
+    }
+    if fields.len() != 4 {
+        return None;
+    }
+    Some(TraderLimits {
+        max_open_locks: field(fields[0])?,
+        max_locks_per_good: field(fields[1])?,
+        max_daily_notional: field(fields[2])?,
+        max_inventory_share: field(fields[3])?,
+    })
+}
+
+fn parse_kind(name: &str) -> Option<GoodKind> {
+    match name {
+        "EUR" => Some(GoodKind::EUR),
+        "USD" => Some(GoodKind::USD),
+        "YEN" => Some(GoodKind::YEN),
+        "YUAN" => Some(GoodKind::YUAN),
+        _ => None,
+    }
+}
+
+//stats are keyed by `Op::name`
+fn op_name(name: &str) -> Option<&'static str> {
+    const OPS: [&str; 13] = [
+        "init",
+        "lock_buy",
+        "buy",
+        "lock_sell",
+        "sell",
+        "cancel",
+        "expire",
+        "partial_buy",
+        "partial_sell",
+        "rebalance",
+        "halt",
+        "resume",
+        "note",
+    ];
+    OPS.iter().copied().find(|op| *op == name)
+}
+
+fn mode_index(mode: &str) -> Option<usize> {
+    match mode {
+        "BUY" => Some(0),
+        "SELL" => Some(1),
+        _ => None,
+    }
+}
+
+fn parse_four<T: std::str::FromStr + Copy + Default>(fields: &[&str]) -> Option<[T; 4]> {
+    if fields.len() != 4 {
+        return None;
+    }
+    let mut res = [T::default(); 4];
+    for (i, f) in fields.iter().enumerate() {
+        res[i] = f.parse().ok()?;
+    }
+    Some(res)
+}
+
+fn join<T: Display>(values: impl Iterator<Item = T>) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+fn state_name(state: TokenState) -> &'static str {
+    match state {
+        TokenState::Active => "active",
+        TokenState::Executed => "executed",
+        TokenState::Expired => "expired",
+        TokenState::Cancelled => "cancelled",
+    }
+}
+
+fn parse_state(name: &str) -> Option<TokenState> {
+    match name {
+        "active" => Some(TokenState::Active),
+        "executed" => Some(TokenState::Executed),
+        "expired" => Some(TokenState::Expired),
+        "cancelled" => Some(TokenState::Cancelled),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unitn_market_2022::good::good::Good;
+    use unitn_market_2022::good::good_kind::GoodKind;
+    use unitn_market_2022::market::Market;
+
+    use crate::oracle::{RateOracle, RateSource};
+
+    use super::super::{LogFormat, ZseConfig, ZSE};
+
+    #[test]
+    fn restored_market_goes_on_like_the_original() {
+        let config = ZseConfig::builder().rates(RateSource::Ema).log_format(LogFormat::Off).build();
+        let market = ZSE::with_config(10000.0, 10000.0, 10000.0, 10000.0, config, 7);
+        {
+            let mut m = market.borrow_mut();
+            let bid = m.get_buy_price(GoodKind::USD, 10.0).unwrap();
+            let token = m.lock_buy(GoodKind::USD, 10.0, bid, "tester".to_string()).unwrap();
+            m.buy(token, &mut Good::new(GoodKind::EUR, bid * 2.0)).unwrap();
+        }
+
+        let path = std::env::temp_dir().join("zse_snapshot_round_trip.txt");
+        let path = path.to_str().unwrap();
+        market.borrow_mut().save_snapshot(path).unwrap();
+        let restored = ZSE::load_snapshot(path).unwrap();
+        {
+            let (a, b) = (market.borrow(), restored.borrow());
+            assert_eq!(a.stats().bought, b.stats().bought);
+            assert_eq!(a.rates.rate(GoodKind::USD), b.rates.rate(GoodKind::USD));
+            assert_eq!(a.traders.volume("tester"), b.traders.volume("tester"));
+        }
+
+        //same seed, clock and prices: the next lock gets the same token at the same price
+        let next: Vec<(String, f32)> = [&market, &restored]
+            .iter()
+            .map(|m| {
+                let mut m = m.borrow_mut();
+                let bid = m.get_buy_price(GoodKind::YEN, 100.0).unwrap();
+                (m.lock_buy(GoodKind::YEN, 100.0, bid, "tester".to_string()).unwrap(), bid)
+            })
+            .collect();
+        assert_eq!(next[0], next[1]);
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use super::log::{variant_name, LogRecord, Op};
+use super::ZSE;
+
+/// What happened at ZSE since the market was created or `ZSE::reset_stats` was last called.
+/// Goods are always EUR USD YEN YUAN.
+#[derive(Clone, Debug, Default)]
+pub struct ZseStats {
+    /// quantity of each good traders bought from us, partial fills included
+    pub bought: [f32; 4],
+    /// quantity of each good traders sold to us
+    pub sold: [f32; 4],
+    /// successful operations by op (`lock_buy`, `buy`, `cancel`, ... as in JSON logs)
+    pub succeeded: HashMap<&'static str, u32>,
+    /// failed operations by op and error variant
+    pub failed: HashMap<(&'static str, String), u32>,
+    /// mean relative gap between our buy and sell rate of each good, over every event seen
+    pub average_spread: [f32; 4],
+    /// mean share of lock slots in use, over every event seen
+    pub lock_utilisation: f32,
+    pub expired_locks: u32,
+    pub rebalances: u32,
+    /// EUR that went to the fee account
+    pub fee_income: f32,
+    /// EUR the fee account holds, since the market was created, `get_goods` never includes it
+    pub fee_account: f32,
+    /// events the averages are taken over
+    pub events: u64,
+}
+
+//running sums behind ZseStats, averages are taken when asked for.
+//Counted where each operation happens, so they don't depend on what gets logged
+#[derive(Default)]
+pub(super) struct StatsBook {
+    pub(super) stats: ZseStats,
+    pub(super) spread_sum: [f64; 4],
+    pub(super) utilisation_sum: f64,
+}
+
+impl StatsBook {
+    pub fn ok(&mut self, op: &Op) {
+        *self.stats.succeeded.entry(op.name()).or_insert(0) += 1;
+    }
+
+    pub fn failed<E: Debug>(&mut self, op: &Op, error: &E) {
+        *self.stats.failed.entry((op.name(), variant_name(error))).or_insert(0) += 1;
+    }
+
+    /// `op` is `Buy` or `PartialBuy`, quantity of goods[index] a trader got from us.
+    pub fn bought(&mut self, op: &Op, index: usize, quantity: f32, fee: f32) {
+        self.ok(op);
+        self.stats.bought[index] += quantity;
+        self.stats.fee_income += fee;
+    }
+
+    pub fn sold(&mut self, op: &Op, index: usize, quantity: f32, fee: f32) {
+        self.ok(op);
+        self.stats.sold[index] += quantity;
+        self.stats.fee_income += fee;
+    }
+
+    pub fn expired(&mut self) {
+        self.ok(&Op::Expire);
+        self.stats.expired_locks += 1;
+    }
+
+    pub fn rebalanced(&mut self, op: &Op) {
+        self.ok(op);
+        self.stats.rebalances += 1;
+    }
+}
+
+impl ZSE {
+    pub fn stats(&self) -> ZseStats {
+        let book = &self.stats;
+        let mut stats = book.stats.clone();
+        stats.fee_account = self.fee_account;
+        if stats.events > 0 {
+            let events = stats.events as f64;
+            for i in 0..4 {
+                stats.average_spread[i] = (book.spread_sum[i] / events) as f32;
+            }
+            stats.lock_utilisation = (book.utilisation_sum / events) as f32;
+        }
+        stats
+    }
+
+    /// Starts a new window, e.g. one per chart point.
+    pub fn reset_stats(&mut self) {
+        self.stats = StatsBook::default();
+    }
+
+    //stats are counted by the caller
+    pub(super) fn record(&mut self, record: &LogRecord) {
+        self.logger.record(record);
+    }
+
+    /// Samples spreads and lock usage, called once per event.
+    pub(super) fn sample_stats(&mut self) {
+        for i in 0..4 {
+            let (buy, sell) = (self.prices_buy[i], self.prices_sell[i]);
+            if buy > 0.0 && sell > 0.0 {
+                self.stats.spread_sum[i] += ((sell - buy).abs() / buy.max(sell)) as f64;
+            }
+        }
+        let slots: usize = self.lock_buy.iter().chain(self.lock_sell.iter()).map(|l| l.lock.len()).sum();
+        if slots > 0 {
+            let used = self.lock_buy.iter().chain(self.lock_sell.iter()).flat_map(|l| l.lock.iter()).filter(|c| !c.token.is_empty()).count();
+            self.stats.utilisation_sum += used as f64 / slots as f64;
+        }
+        self.stats.stats.events += 1;
+    }
+}
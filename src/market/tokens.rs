@@ -0,0 +1,157 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::{Mode, TokenState};
+
+/// Everything the market knows about a token it handed out.
+#[derive(Clone, Debug)]
+pub(super) struct TokenEntry {
+    /// good index, EUR USD YEN YUAN
+    pub index: usize,
+    /// position of the contract inside its `Lock`
+    pub slot: usize,
+    pub mode: Mode,
+    pub state: TokenState,
+    pub trader: String,
+    /// market tick the lock was taken at
+    pub created_at: u64,
+}
+
+/// Token -> contract lookup, so buy/sell never have to scan the lock arrays.
+/// Live tokens are always kept, finished ones only until `retention` newer ones finished after them.
+pub(super) struct TokenTable {
+    entries: HashMap<String, TokenEntry>,
+    //finished tokens, oldest first
+    retired: VecDeque<String>,
+    retention: usize,
+    now: u64,
+}
+
+impl TokenTable {
+    pub fn new(retention: usize) -> Self {
+        TokenTable {
+            entries: HashMap::new(),
+            retired: VecDeque::new(),
+            retention,
+            now: 0,
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn tick(&mut self) {
+        self.now += 1;
+    }
+
+    pub fn insert(&mut self, token: &str, index: usize, slot: usize, mode: Mode, trader: &str) {
+        let entry = TokenEntry {
+            index,
+            slot,
+            mode,
+            state: TokenState::Active,
+            trader: trader.to_string(),
+            created_at: self.now,
+        };
+        self.entries.insert(token.to_string(), entry);
+    }
+
+    pub fn get(&self, token: &str) -> Option<&TokenEntry> {
+        self.entries.get(token)
+    }
+
+    /// Good index and slot of an active token of the given mode.
+    /// Err(None) if we never issued it for that mode (or forgot it), otherwise the state it is in.
+    pub fn live(&self, token: &str, mode: Mode) -> Result<(usize, usize), Option<TokenState>> {
+        match self.entries.get(token) {
+            Some(entry) if entry.mode == mode => match entry.state {
+                TokenState::Active => Ok((entry.index, entry.slot)),
+                state => Err(Some(state)),
+            },
+            _ => Err(None),
+        }
+    }
+
+    /// Moves an active token to a final state and forgets the oldest finished ones past the retention.
+    pub fn retire(&mut self, token: &str, state: TokenState) {
+        match self.entries.get_mut(token) {
+            Some(entry) if entry.state == TokenState::Active => entry.state = state,
+            _ => return,
+        }
+        self.retired.push_back(token.to_string());
+        while self.retired.len() > self.retention {
+            if let Some(old) = self.retired.pop_front() {
+                self.entries.remove(&old);
+            }
+        }
+    }
+
+    /// Puts back an entry read from a snapshot, finished ones keep their order.
+    pub fn restore(&mut self, token: String, entry: TokenEntry) {
+        if entry.state != TokenState::Active {
+            self.retired.push_back(token.clone());
+        }
+        self.entries.insert(token, entry);
+    }
+
+    pub fn restore_clock(&mut self, now: u64) {
+        self.now = now;
+    }
+
+    /// Active tokens first, then the finished ones oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &TokenEntry)> {
+        let active = self.entries.iter().filter(|(_, e)| e.state == TokenState::Active);
+        let retired = self.retired.iter().filter_map(move |t| self.entries.get_key_value(t));
+        active.chain(retired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unitn_market_2022::good::good::Good;
+    use unitn_market_2022::good::good_kind::GoodKind;
+    use unitn_market_2022::market::{BuyError, Market};
+
+    use super::super::{LogFormat, Mode, TokenState, ZseConfig, ZSE};
+    use super::TokenTable;
+
+    #[test]
+    fn only_the_newest_finished_tokens_are_kept() {
+        let mut table = TokenTable::new(2);
+        for token in ["a", "b", "c", "live"] {
+            table.insert(token, 1, 0, Mode::Buy, "tester");
+        }
+        table.retire("a", TokenState::Executed);
+        table.retire("b", TokenState::Expired);
+        //a second retire of the same token is ignored, it must not push `a` out twice
+        table.retire("b", TokenState::Cancelled);
+        assert_eq!(table.live("a", Mode::Buy), Err(Some(TokenState::Executed)));
+
+        table.retire("c", TokenState::Cancelled);
+        assert_eq!(table.live("a", Mode::Buy), Err(None));
+        assert_eq!(table.live("b", Mode::Buy), Err(Some(TokenState::Expired)));
+        assert_eq!(table.live("c", Mode::Buy), Err(Some(TokenState::Cancelled)));
+        //live tokens are never dropped, however many finish after them
+        assert_eq!(table.live("live", Mode::Buy), Ok((1, 0)));
+        assert_eq!(table.live("live", Mode::Sell), Err(None));
+    }
+
+    #[test]
+    fn a_forgotten_token_is_unrecognized() {
+        let config = ZseConfig::builder().token_retention(1).log_format(LogFormat::Off).build();
+        let market = ZSE::with_config(10000.0, 10000.0, 10000.0, 10000.0, config, 0);
+        let mut m = market.borrow_mut();
+        let mut cash = Good::new(GoodKind::EUR, 10000.0);
+
+        let mut executed = Vec::new();
+        for _ in 0..2 {
+            let bid = m.get_buy_price(GoodKind::USD, 10.0).unwrap();
+            let token = m.lock_buy(GoodKind::USD, 10.0, bid, "tester".to_string()).unwrap();
+            m.buy(token.clone(), &mut cash).unwrap();
+            executed.push(token);
+        }
+
+        assert!(matches!(m.buy(executed[1].clone(), &mut cash), Err(BuyError::ExpiredToken { .. })));
+        assert!(matches!(m.buy(executed[0].clone(), &mut cash), Err(BuyError::UnrecognizedToken { .. })));
+    }
+}
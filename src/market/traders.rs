@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use unitn_market_2022::good::good::Good;
+use unitn_market_2022::good::good_kind::GoodKind;
+use unitn_market_2022::market::{BuyError, LockBuyError, LockSellError, Market, SellError};
+
+use super::breaker::HaltReason;
+use super::log::{LogRecord, Op};
+use super::{Mode, ZSE};
+
+/// What a single trader is allowed to hold at ZSE, `None` means no limit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraderLimits {
+    /// locks open at the same time, every good and side together
+    pub max_open_locks: Option<usize>,
+    /// locks open at the same time on one side of one good
+    pub max_locks_per_good: Option<usize>,
+    /// EUR locked (bids plus offers) in a single market day
+    pub max_daily_notional: Option<f32>,
+    /// fraction of our stock of a good (EUR for sell locks) one trader may keep reserved
+    pub max_inventory_share: Option<f32>,
+}
+
+/// Errors of the trader aware entry points (`try_lock_buy`, `try_lock_sell`, `buy_as`, `sell_as`).
+/// The plain `Market` trait only gets the closest library error, see `lock_buy_refusal`,
+/// our own traders go through these via `MarketRegistry`.
+#[derive(Debug)]
+pub enum ZseTraderError {
+    LockBuy(LockBuyError),
+    LockSell(LockSellError),
+    Buy(BuyError),
+    Sell(SellError),
+    TooManyOpenLocks { trader: String, open: usize, max: usize },
+    TooManyLocksOnGood { trader: String, kind: GoodKind, open: usize, max: usize },
+    DailyNotionalExceeded { trader: String, used: f32, requested: f32, max: f32 },
+    InventoryShareExceeded { trader: String, kind: GoodKind, share: f32, max: f32 },
+    NotTokenOwner { token: String, trader: String },
+    /// a circuit breaker stopped trading, see `ZSE::halted`
+    Halted { reason: HaltReason },
+}
+
+impl Display for ZseTraderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZseTraderError::LockBuy(e) => write!(f, "{:?}", e),
+            ZseTraderError::LockSell(e) => write!(f, "{:?}", e),
+            ZseTraderError::Buy(e) => write!(f, "{:?}", e),
+            ZseTraderError::Sell(e) => write!(f, "{:?}", e),
+            ZseTraderError::TooManyOpenLocks { trader, open, max } => write!(f, "{} already holds {} locks, limit is {}", trader, open, max),
+            ZseTraderError::TooManyLocksOnGood { trader, kind, open, max } => write!(f, "{} already holds {} locks on {}, limit is {}", trader, open, kind, max),
+            ZseTraderError::DailyNotionalExceeded { trader, used, requested, max } => write!(f, "{} locked {} EUR today, {} more would pass the daily {}", trader, used, requested, max),
+            ZseTraderError::InventoryShareExceeded { trader, kind, share, max } => write!(f, "{} would reserve {:.1}% of our {}, limit is {:.1}%", trader, share * 100.0, kind, max * 100.0),
+            ZseTraderError::NotTokenOwner { token, trader } => write!(f, "token {} was not issued to {}", token, trader),
+            ZseTraderError::Halted { reason } => write!(f, "trading is halted: {}", reason),
+        }
+    }
+}
+
+/// EUR each trader locked since the last market day, and traded with us since the market opened.
+#[derive(Default)]
+pub(super) struct TraderBook {
+    notional: HashMap<String, f32>,
+    //picks the fee tier, never reset
+    volume: HashMap<String, f32>,
+}
+
+impl TraderBook {
+    pub fn notional(&self, trader: &str) -> f32 {
+        self.notional.get(trader).copied().unwrap_or(0.0)
+    }
+
+    pub fn add_notional(&mut self, trader: &str, amount: f32) {
+        *self.notional.entry(trader.to_string()).or_insert(0.0) += amount;
+    }
+
+    pub fn volume(&self, trader: &str) -> f32 {
+        self.volume.get(trader).copied().unwrap_or(0.0)
+    }
+
+    pub fn add_volume(&mut self, trader: &str, amount: f32) {
+        *self.volume.entry(trader.to_string()).or_insert(0.0) += amount;
+    }
+
+    pub fn new_day(&mut self) {
+        self.notional.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &f32)> {
+        self.notional.iter()
+    }
+
+    pub fn volumes(&self) -> impl Iterator<Item = (&String, &f32)> {
+        self.volume.iter()
+    }
+}
+
+impl ZSE {
+    pub fn limits_for(&self, trader: &str) -> &TraderLimits {
+        self.config.trader_limits_for.get(trader).unwrap_or(&self.config.trader_limits)
+    }
+
+    /// Name the token was issued to, while we still remember it.
+    pub fn token_owner(&self, token: &str) -> Option<&str> {
+        self.token.get(token).map(|e| e.trader.as_str())
+    }
+
+    /// `lock_buy` that tells which trader limit or halt was hit.
+    pub fn try_lock_buy(&mut self, kind_to_buy: GoodKind, quantity_to_buy: f32, bid: f32, trader_name: String) -> Result<String, ZseTraderError> {
+        let record = LogRecord::new(Op::LockBuy).trader(&trader_name).kind(kind_to_buy).qty(quantity_to_buy).price(bid);
+        let index = self.get_index_by_goodkind(&kind_to_buy);
+        if let Err(e) = self.check_halt().and_then(|_| self.check_trader_limits(&trader_name, Mode::Buy, index, quantity_to_buy, bid)) {
+            return self.reject(record, e);
+        }
+        self.lock_buy(kind_to_buy, quantity_to_buy, bid, trader_name).map_err(ZseTraderError::LockBuy)
+    }
+
+    /// `lock_sell` that tells which trader limit or halt was hit.
+    pub fn try_lock_sell(&mut self, kind_to_sell: GoodKind, quantity_to_sell: f32, offer: f32, trader_name: String) -> Result<String, ZseTraderError> {
+        let record = LogRecord::new(Op::LockSell).trader(&trader_name).kind(kind_to_sell).qty(quantity_to_sell).price(offer);
+        let index = self.get_index_by_goodkind(&kind_to_sell);
+        if let Err(e) = self.check_halt().and_then(|_| self.check_trader_limits(&trader_name, Mode::Sell, index, quantity_to_sell, offer)) {
+            return self.reject(record, e);
+        }
+        self.lock_sell(kind_to_sell, quantity_to_sell, offer, trader_name).map_err(ZseTraderError::LockSell)
+    }
+
+    /// `buy` that only goes through for the trader who locked the token.
+    pub fn buy_as(&mut self, trader_name: &str, token: String, cash: &mut Good) -> Result<Good, ZseTraderError> {
+        self.check_owner(Op::Buy, trader_name, &token)?;
+        self.buy(token, cash).map_err(ZseTraderError::Buy)
+    }
+
+    /// `sell` that only goes through for the trader who locked the token.
+    pub fn sell_as(&mut self, trader_name: &str, token: String, good: &mut Good) -> Result<Good, ZseTraderError> {
+        self.check_owner(Op::Sell, trader_name, &token)?;
+        self.sell(token, good).map_err(ZseTraderError::Sell)
+    }
+
+    //unknown tokens are left to buy/sell, they already know how to refuse them
+    fn check_owner(&mut self, op: Op, trader_name: &str, token: &str) -> Result<(), ZseTraderError> {
+        let foreign = self.token_owner(token).map_or(false, |owner| owner != trader_name);
+        if foreign {
+            let record = LogRecord::new(op).trader(trader_name).token(token);
+            return self.reject(record, ZseTraderError::NotTokenOwner { token: token.to_string(), trader: trader_name.to_string() });
+        }
+        Ok(())
+    }
+
+    /// Checks a new lock of `quantity` for `price` EUR against the limits of `trader`.
+    pub(super) fn check_trader_limits(&self, trader: &str, mode: Mode, index: usize, quantity: f32, price: f32) -> Result<(), ZseTraderError> {
+        let limits = self.limits_for(trader);
+        let kind = self.goods[index].get_kind();
+
+        if let Some(max) = limits.max_open_locks {
+            let open = self.open_locks_of(trader, None);
+            if open >= max {
+                return Err(ZseTraderError::TooManyOpenLocks { trader: trader.to_string(), open, max });
+            }
+        }
+        if let Some(max) = limits.max_locks_per_good {
+            let open = self.open_locks_of(trader, Some((mode, index)));
+            if open >= max {
+                return Err(ZseTraderError::TooManyLocksOnGood { trader: trader.to_string(), kind, open, max });
+            }
+        }
+        if let Some(max) = limits.max_daily_notional {
+            let used = self.traders.notional(trader);
+            if used + price > max {
+                return Err(ZseTraderError::DailyNotionalExceeded { trader: trader.to_string(), used, requested: price, max });
+            }
+        }
+        if let Some(max) = limits.max_inventory_share {
+            //buy locks reserve the good, sell locks reserve our EUR
+            let (held, asked, stock) = match mode {
+                Mode::Buy => (self.reserved_by(trader, Mode::Buy, index), quantity, self.goods[index].get_qty()),
+                Mode::Sell => (self.reserved_by(trader, Mode::Sell, index), price, self.goods[0].get_qty()),
+            };
+            let share = if stock > 0.0 { (held + asked) / stock } else { f32::INFINITY };
+            if share > max {
+                return Err(ZseTraderError::InventoryShareExceeded { trader: trader.to_string(), kind, share, max });
+            }
+        }
+        Ok(())
+    }
+
+    /// What the `Market` trait reports for a breach: lock counts are `MaxAllowedLocksReached`,
+    /// EUR and stock limits report the quantity still allowed as available, a halt allows nothing.
+    pub(super) fn lock_buy_refusal(&self, error: &ZseTraderError, trader: &str, index: usize, quantity: f32, bid: f32) -> LockBuyError {
+        let available = match error {
+            ZseTraderError::DailyNotionalExceeded { used, max, .. } if bid > 0.0 => quantity * ((max - used) / bid).clamp(0.0, 1.0),
+            ZseTraderError::InventoryShareExceeded { max, .. } => (max * self.goods[index].get_qty() - self.reserved_by(trader, Mode::Buy, index)).max(0.0),
+            ZseTraderError::DailyNotionalExceeded { .. } | ZseTraderError::Halted { .. } => 0.0,
+            _ => return LockBuyError::MaxAllowedLocksReached,
+        };
+        LockBuyError::InsufficientGoodQuantityAvailable { requested_good_kind: self.goods[index].get_kind(), requested_good_quantity: quantity, available_good_quantity: available }
+    }
+
+    /// Same as `lock_buy_refusal`, sell limits are on our EUR so the remainder is in EUR.
+    pub(super) fn lock_sell_refusal(&self, error: &ZseTraderError, trader: &str, quantity: f32, kind: GoodKind) -> LockSellError {
+        let available = match error {
+            ZseTraderError::DailyNotionalExceeded { used, max, .. } => (max - used).max(0.0),
+            ZseTraderError::InventoryShareExceeded { max, .. } => (max * self.goods[0].get_qty() - self.reserved_by(trader, Mode::Sell, 0)).max(0.0),
+            ZseTraderError::Halted { .. } => 0.0,
+            _ => return LockSellError::MaxAllowedLocksReached,
+        };
+        LockSellError::InsufficientDefaultGoodQuantityAvailable { offered_good_kind: kind, offered_good_quantity: quantity, available_good_quantity: available }
+    }
+
+    //live locks of trader, all of them or only one side of one good
+    fn open_locks_of(&self, trader: &str, only: Option<(Mode, usize)>) -> usize {
+        let mut open = 0;
+        for (mode, locks) in [(Mode::Buy, &self.lock_buy), (Mode::Sell, &self.lock_sell)] {
+            for (i, lock) in locks.iter().enumerate() {
+                if only.map_or(false, |(m, g)| m != mode || g != i) {
+                    continue;
+                }
+                open += lock.lock.iter().filter(|c| !c.token.is_empty() && self.token_owner(&c.token) == Some(trader)).count();
+            }
+        }
+        open
+    }
+
+    //what trader's live locks keep reserved: the good for buy locks, EUR for sell locks (summed over every good)
+    fn reserved_by(&self, trader: &str, mode: Mode, index: usize) -> f32 {
+        match mode {
+            Mode::Buy => self.lock_buy[index]
+                .lock
+                .iter()
+                .filter(|c| !c.token.is_empty() && self.token_owner(&c.token) == Some(trader))
+                .map(|c| c.quantity)
+                .sum(),
+            Mode::Sell => self
+                .lock_sell
+                .iter()
+                .flat_map(|l| l.lock.iter())
+                .filter(|c| !c.token.is_empty() && self.token_owner(&c.token) == Some(trader))
+                .map(|c| c.price)
+                .sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use unitn_market_2022::event::event::{Event, EventKind};
+    use unitn_market_2022::event::notifiable::Notifiable;
+    use unitn_market_2022::good::good::Good;
+    use unitn_market_2022::good::good_kind::GoodKind;
+    use unitn_market_2022::market::{LockBuyError, LockSellError, Market};
+
+    use super::super::{CancelLock, CancelLockError, LogFormat, ZseConfig, ZSE};
+    use super::{TraderLimits, ZseTraderError};
+
+    fn market(limits: TraderLimits) -> Rc<RefCell<ZSE>> {
+        let config = ZseConfig::builder().trader_limits(limits).log_format(LogFormat::Off).build();
+        ZSE::with_config(10000.0, 10000.0, 10000.0, 10000.0, config, 0)
+    }
+
+    fn lock_buy(m: &mut ZSE, kind: GoodKind, quantity: f32, trader: &str) -> Result<String, ZseTraderError> {
+        let bid = m.get_buy_price(kind, quantity).unwrap();
+        m.try_lock_buy(kind, quantity, bid, trader.to_string())
+    }
+
+    fn lock_sell(m: &mut ZSE, kind: GoodKind, quantity: f32, trader: &str) -> Result<String, ZseTraderError> {
+        let offer = m.get_sell_price(kind, quantity).unwrap();
+        m.try_lock_sell(kind, quantity, offer, trader.to_string())
+    }
+
+    #[test]
+    fn open_locks_are_counted_per_trader() {
+        let market = market(TraderLimits { max_open_locks: Some(2), ..Default::default() });
+        let mut m = market.borrow_mut();
+
+        lock_buy(&mut m, GoodKind::USD, 10.0, "a").unwrap();
+        lock_sell(&mut m, GoodKind::YEN, 10.0, "a").unwrap();
+        let res = lock_buy(&mut m, GoodKind::YUAN, 10.0, "a");
+        assert!(matches!(res, Err(ZseTraderError::TooManyOpenLocks { open: 2, max: 2, .. })), "{:?}", res);
+        let bid = m.get_buy_price(GoodKind::YUAN, 10.0).unwrap();
+        assert!(matches!(m.lock_buy(GoodKind::YUAN, 10.0, bid, "a".to_string()), Err(LockBuyError::MaxAllowedLocksReached)));
+
+        lock_buy(&mut m, GoodKind::YUAN, 10.0, "b").unwrap();
+    }
+
+    #[test]
+    fn locks_per_good_count_one_side() {
+        let market = market(TraderLimits { max_locks_per_good: Some(1), ..Default::default() });
+        let mut m = market.borrow_mut();
+
+        lock_buy(&mut m, GoodKind::USD, 10.0, "a").unwrap();
+        lock_sell(&mut m, GoodKind::USD, 10.0, "a").unwrap();
+        let res = lock_sell(&mut m, GoodKind::USD, 10.0, "a");
+        assert!(matches!(res, Err(ZseTraderError::TooManyLocksOnGood { kind: GoodKind::USD, open: 1, max: 1, .. })), "{:?}", res);
+        let offer = m.get_sell_price(GoodKind::USD, 10.0).unwrap();
+        assert!(matches!(m.lock_sell(GoodKind::USD, 10.0, offer, "a".to_string()), Err(LockSellError::MaxAllowedLocksReached)));
+    }
+
+    #[test]
+    fn daily_notional_reports_what_is_left_and_resets_on_wait() {
+        let market = market(TraderLimits { max_daily_notional: Some(1000.0), ..Default::default() });
+        let mut m = market.borrow_mut();
+
+        //bids above the quote are fine, the limit counts what is bid
+        m.try_lock_buy(GoodKind::USD, 1.0, 600.0, "a".to_string()).unwrap();
+        let res = m.try_lock_buy(GoodKind::USD, 1.0, 600.0, "a".to_string());
+        assert!(matches!(res, Err(ZseTraderError::DailyNotionalExceeded { .. })), "{:?}", res);
+        match m.lock_buy(GoodKind::USD, 3.0, 600.0, "a".to_string()) {
+            Err(LockBuyError::InsufficientGoodQuantityAvailable { available_good_quantity, .. }) => assert!((available_good_quantity - 2.0).abs() < 1e-4),
+            other => panic!("{:?}", other),
+        }
+
+        m.on_event(Event { kind: EventKind::Wait, good_kind: GoodKind::EUR, quantity: 0.0, price: 0.0 });
+        m.try_lock_buy(GoodKind::USD, 1.0, 600.0, "a".to_string()).unwrap();
+    }
+
+    #[test]
+    fn inventory_share_reports_the_allowed_remainder() {
+        let market = market(TraderLimits { max_inventory_share: Some(0.1), ..Default::default() });
+        let mut m = market.borrow_mut();
+
+        lock_buy(&mut m, GoodKind::USD, 800.0, "a").unwrap();
+        let res = lock_buy(&mut m, GoodKind::USD, 300.0, "a");
+        assert!(matches!(res, Err(ZseTraderError::InventoryShareExceeded { kind: GoodKind::USD, .. })), "{:?}", res);
+        let bid = m.get_buy_price(GoodKind::USD, 300.0).unwrap();
+        match m.lock_buy(GoodKind::USD, 300.0, bid, "a".to_string()) {
+            Err(LockBuyError::InsufficientGoodQuantityAvailable { available_good_quantity, .. }) => assert!((available_good_quantity - 200.0).abs() < 1e-2),
+            other => panic!("{:?}", other),
+        }
+
+        //sell locks reserve our EUR
+        let offer = m.get_sell_price(GoodKind::USD, 800.0).unwrap();
+        m.try_lock_sell(GoodKind::USD, 800.0, offer, "a".to_string()).unwrap();
+        match m.lock_sell(GoodKind::USD, 800.0, offer, "a".to_string()) {
+            Err(LockSellError::InsufficientDefaultGoodQuantityAvailable { available_good_quantity, .. }) => assert!((available_good_quantity - (1000.0 - offer)).abs() < 1e-2),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn only_the_owner_uses_a_token() {
+        let market = market(TraderLimits::default());
+        let mut m = market.borrow_mut();
+
+        let buy_token = lock_buy(&mut m, GoodKind::USD, 10.0, "a").unwrap();
+        let sell_token = lock_sell(&mut m, GoodKind::USD, 10.0, "a").unwrap();
+        let mut cash = Good::new(GoodKind::EUR, 10000.0);
+        let mut usd = Good::new(GoodKind::USD, 10.0);
+
+        assert!(matches!(m.buy_as("b", buy_token.clone(), &mut cash), Err(ZseTraderError::NotTokenOwner { .. })));
+        assert!(matches!(m.sell_as("b", sell_token.clone(), &mut usd), Err(ZseTraderError::NotTokenOwner { .. })));
+        assert!(matches!(m.cancel_lock(&buy_token, "b"), Err(CancelLockError::NotTokenOwner { .. })));
+
+        //the refusals left the locks alone
+        m.buy_as("a", buy_token, &mut cash).unwrap();
+        m.sell_as("a", sell_token, &mut usd).unwrap();
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+
+use unitn_market_2022::good::good::Good;
+use unitn_market_2022::good::good_kind::GoodKind;
+
+use super::log::{LogRecord, Op};
+use super::ZSE;
+
+//rebalances kept for `ZSE::rebalances`, the log has all of them
+const REBALANCE_HISTORY: usize = 100;
+
+/// Where ZSE wants its inventory, as shares of the EUR value of what is not locked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreasuryTargets {
+    /// EUR USD YEN YUAN, normalized before use so they don't have to sum to 1
+    pub weights: [f32; 4],
+    /// how far a share may drift from its weight before we convert
+    pub tolerance: f32,
+    /// fraction of the converted value lost to the conversion
+    pub cost: f32,
+}
+
+impl Default for TreasuryTargets {
+    fn default() -> Self {
+        TreasuryTargets {
+            weights: [0.25; 4],
+            tolerance: 0.15,
+            cost: 0.005,
+        }
+    }
+}
+
+/// One internal conversion, quantities in units of their own good.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rebalance {
+    pub from: GoodKind,
+    pub sold: f32,
+    pub to: GoodKind,
+    pub bought: f32,
+    /// EUR value lost to `TreasuryTargets::cost`
+    pub cost: f32,
+}
+
+/// Last rebalances of a market, oldest first.
+#[derive(Default)]
+pub(super) struct RebalanceHistory {
+    entries: VecDeque<Rebalance>,
+}
+
+impl RebalanceHistory {
+    pub(super) fn push(&mut self, rebalance: Rebalance) {
+        if self.entries.len() == REBALANCE_HISTORY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(rebalance);
+    }
+}
+
+impl ZSE {
+    /// Share of the unlocked inventory each good holds, valued in EUR by the market's rate oracle.
+    pub fn treasury_weights(&self) -> [f32; 4] {
+        let values = self.free_values();
+        let total: f32 = values.iter().sum();
+        if total <= 0.0 {
+            return [0.0; 4];
+        }
+        values.map(|v| v / total)
+    }
+
+    /// Most recent internal conversions, oldest first.
+    pub fn rebalances(&self) -> impl Iterator<Item = &Rebalance> {
+        self.rebalances.entries.iter()
+    }
+
+    /// Moves value from the most overweight good to the most underweight one when either is outside the tolerance band.
+    /// Only what is not locked is counted and converted, each pair of goods waits `conversion_cooldown` events between conversions.
+    pub(super) fn rebalance(&mut self) -> Option<Rebalance> {
+        let targets = &self.config.treasury;
+        let target_sum: f32 = targets.weights.iter().sum();
+        if target_sum <= 0.0 {
+            return None;
+        }
+        let values = self.free_values();
+        let total: f32 = values.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+
+        let deviation: Vec<f32> = (0..4).map(|i| values[i] / total - targets.weights[i] / target_sum).collect();
+        let mut from = 0;
+        let mut to = 0;
+        for i in 1..4 {
+            if deviation[i] > deviation[from] {
+                from = i;
+            }
+            if deviation[i] < deviation[to] {
+                to = i;
+            }
+        }
+        if from == to || (deviation[from] <= targets.tolerance && deviation[to] >= -targets.tolerance) {
+            return None;
+        }
+        if self.conversion_timer[from][to] > 0 {
+            return None;
+        }
+
+        //only as much as brings the closer of the two back on target
+        let eur = deviation[from].min(-deviation[to]) * total;
+        let from_kind = self.goods[from].get_kind();
+        let to_kind = self.goods[to].get_kind();
+        let sold = self.rates.from_eur(from_kind, eur);
+        let bought = self.rates.from_eur(to_kind, eur * (1.0 - targets.cost));
+        let cost = eur * targets.cost;
+        if !(sold > 0.0 && bought > 0.0 && sold.is_finite() && bought.is_finite()) {
+            return None;
+        }
+
+        self.goods[from].split(sold).ok()?;
+        let _ = self.goods[to].merge(Good::new(to_kind, bought));
+        self.conversion_timer[from][to] = self.config.conversion_cooldown;
+
+        let rebalance = Rebalance { from: from_kind, sold, to: to_kind, bought, cost };
+        let record = LogRecord::new(Op::Rebalance(rebalance.clone()));
+        self.stats.rebalanced(&record.op);
+        self.record(&record);
+        self.rebalances.push(rebalance.clone());
+        Some(rebalance)
+    }
+
+    //EUR value of the part of each good no lock holds
+    fn free_values(&self) -> [f32; 4] {
+        let mut values = [0.0; 4];
+        for (i, good) in self.goods.iter().enumerate() {
+            let free = (good.get_qty() - self.locked_qty[i]).max(0.0);
+            values[i] = self.rates.to_eur(&Good::new(good.get_kind(), free));
+        }
+        values
+    }
+}
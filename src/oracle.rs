@@ -0,0 +1,217 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use unitn_market_2022::good::consts::{
+    DEFAULT_EUR_USD_EXCHANGE_RATE, DEFAULT_EUR_YEN_EXCHANGE_RATE, DEFAULT_EUR_YUAN_EXCHANGE_RATE,
+};
+use unitn_market_2022::good::{good::Good, good_kind::GoodKind};
+use unitn_market_2022::market::Market;
+
+/// Where EUR values come from.
+/// Rates follow the `DEFAULT_EUR_*_EXCHANGE_RATE` convention: units of the good per EUR, EUR is always 1.
+pub trait RateOracle {
+    fn rate(&self, kind: GoodKind) -> f32;
+
+    /// Called with every executed trade the owner sees, price in EUR.
+    fn observe_trade(&mut self, _kind: GoodKind, _quantity: f32, _price: f32) {}
+
+    fn to_eur(&self, good: &Good) -> f32 {
+        good.get_qty() / self.rate(good.get_kind())
+    }
+
+    fn from_eur(&self, kind: GoodKind, eur: f32) -> f32 {
+        eur * self.rate(kind)
+    }
+
+    /// What a snapshot needs to rebuild the oracle, empty when it keeps nothing.
+    fn state(&self) -> Vec<f32> {
+        Vec::new()
+    }
+
+    /// Puts back what `state` returned.
+    fn restore(&mut self, _state: &[f32]) {}
+}
+
+/// Which oracle a ZSE market values its holdings with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateSource {
+    Static,
+    /// EMA of the trades executed at ZSE
+    Ema,
+    /// average mid quote of the other markets in the `MarketRegistry`, the constants until it hands them over
+    MidPrice,
+}
+
+impl RateSource {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "static" => Some(RateSource::Static),
+            "ema" => Some(RateSource::Ema),
+            "mid_price" => Some(RateSource::MidPrice),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RateSource::Static => "static",
+            RateSource::Ema => "ema",
+            RateSource::MidPrice => "mid_price",
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn RateOracle> {
+        match self {
+            RateSource::Static => Box::new(StaticRates),
+            RateSource::Ema => Box::new(EmaRates::default()),
+            RateSource::MidPrice => Box::new(MidPriceRates::new(Vec::new())),
+        }
+    }
+}
+
+pub fn default_rate(kind: GoodKind) -> f32 {
+    match kind {
+        GoodKind::EUR => 1.0,
+        GoodKind::USD => DEFAULT_EUR_USD_EXCHANGE_RATE,
+        GoodKind::YEN => DEFAULT_EUR_YEN_EXCHANGE_RATE,
+        GoodKind::YUAN => DEFAULT_EUR_YUAN_EXCHANGE_RATE,
+    }
+}
+
+fn index_of(kind: GoodKind) -> usize {
+    match kind {
+        GoodKind::EUR => 0,
+        GoodKind::USD => 1,
+        GoodKind::YEN => 2,
+        GoodKind::YUAN => 3,
+    }
+}
+
+/// The library constants, what everything used before.
+pub struct StaticRates;
+
+impl RateOracle for StaticRates {
+    fn rate(&self, kind: GoodKind) -> f32 {
+        default_rate(kind)
+    }
+}
+
+/// Average of the mid quotes ((buy + sell) / 2) of a set of markets, asked every time.
+/// Markets quoting nothing usable are skipped, with none left we fall back to the constants.
+pub struct MidPriceRates {
+    markets: Vec<Rc<RefCell<dyn Market>>>,
+}
+
+impl MidPriceRates {
+    pub fn new(markets: Vec<Rc<RefCell<dyn Market>>>) -> Self {
+        MidPriceRates { markets }
+    }
+}
+
+impl RateOracle for MidPriceRates {
+    fn rate(&self, kind: GoodKind) -> f32 {
+        if kind == GoodKind::EUR {
+            return 1.0;
+        }
+        let mids: Vec<f32> = self
+            .markets
+            .iter()
+            //a market busy with a trade of its own can't be asked right now
+            .filter_map(|m| m.try_borrow().ok().map(|m| m.get_goods()))
+            .filter_map(|labels| labels.into_iter().find(|l| l.good_kind == kind))
+            .map(|l| (l.exchange_rate_buy + l.exchange_rate_sell) / 2.0)
+            .filter(|mid| mid.is_finite() && *mid > 0.0)
+            .collect();
+        if mids.is_empty() {
+            return default_rate(kind);
+        }
+        mids.iter().sum::<f32>() / mids.len() as f32
+    }
+}
+
+/// Exponential moving average of the rates trades actually executed at, seeded with the constants.
+/// A trade's rate is units per EUR paid like every other rate here, so a dearer trade lowers it and raises `to_eur`.
+pub struct EmaRates {
+    rates: [f32; 4],
+    /// weight of the newest trade
+    alpha: f32,
+}
+
+impl EmaRates {
+    pub fn new(alpha: f32) -> Self {
+        EmaRates {
+            rates: [1.0, DEFAULT_EUR_USD_EXCHANGE_RATE, DEFAULT_EUR_YEN_EXCHANGE_RATE, DEFAULT_EUR_YUAN_EXCHANGE_RATE],
+            alpha,
+        }
+    }
+}
+
+impl Default for EmaRates {
+    fn default() -> Self {
+        EmaRates::new(0.1)
+    }
+}
+
+impl RateOracle for EmaRates {
+    fn rate(&self, kind: GoodKind) -> f32 {
+        self.rates[index_of(kind)]
+    }
+
+    fn observe_trade(&mut self, kind: GoodKind, quantity: f32, price: f32) {
+        if kind == GoodKind::EUR || quantity <= 0.0 || price <= 0.0 {
+            return;
+        }
+        let i = index_of(kind);
+        self.rates[i] = self.alpha * (quantity / price) + (1.0 - self.alpha) * self.rates[i];
+    }
+
+    fn state(&self) -> Vec<f32> {
+        self.rates.to_vec()
+    }
+
+    fn restore(&mut self, state: &[f32]) {
+        if state.len() == 4 {
+            self.rates.copy_from_slice(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unitn_market_2022::good::good::Good;
+    use unitn_market_2022::good::good_kind::GoodKind;
+
+    use super::{EmaRates, RateOracle, RateSource};
+
+    #[test]
+    fn trade_at_the_rate_keeps_the_rate() {
+        let mut ema = EmaRates::default();
+        for kind in [GoodKind::USD, GoodKind::YEN, GoodKind::YUAN] {
+            let rate = ema.rate(kind);
+            ema.observe_trade(kind, 250.0, 250.0 / rate);
+            assert!((ema.rate(kind) - rate).abs() <= rate * 1e-6, "{:?} moved from {} to {}", kind, rate, ema.rate(kind));
+        }
+    }
+
+    #[test]
+    fn dearer_trade_raises_the_eur_value() {
+        let mut ema = EmaRates::new(0.5);
+        let good = Good::new(GoodKind::USD, 100.0);
+        let (rate, value) = (ema.rate(GoodKind::USD), ema.to_eur(&good));
+        //twice the EUR the rate asks for
+        ema.observe_trade(GoodKind::USD, 100.0, 100.0 / rate * 2.0);
+        assert!(ema.rate(GoodKind::USD) < rate);
+        assert!(ema.to_eur(&good) > value);
+
+        let value = ema.to_eur(&good);
+        ema.observe_trade(GoodKind::USD, 100.0, 100.0 / rate / 2.0);
+        assert!(ema.to_eur(&good) < value);
+    }
+
+    #[test]
+    fn every_source_has_a_name() {
+        for source in [RateSource::Static, RateSource::Ema, RateSource::MidPrice] {
+            assert_eq!(RateSource::from_name(source.name()), Some(source));
+        }
+    }
+}
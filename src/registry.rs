@@ -0,0 +1,335 @@
+use std::cell::RefCell;
+use std::ops::Index;
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+
+use bfb::bfb_market::Bfb;
+use rand::Rng;
+use rcnz_market::rcnz::RCNZ;
+use unitn_market_2022::good::consts::{DEFAULT_EUR_USD_EXCHANGE_RATE, DEFAULT_EUR_YEN_EXCHANGE_RATE, DEFAULT_EUR_YUAN_EXCHANGE_RATE};
+use unitn_market_2022::good::good::Good;
+use unitn_market_2022::good::good_kind::GoodKind;
+use unitn_market_2022::market::{BuyError, Market, SellError};
+use BVC::BVCMarket;
+
+use crate::bus::EventBus;
+use crate::market::{BreakerEvent, CancelLock, CancelLockError, PartialFill, SnapshotError, ZseConfig, ZseStats, ZseTraderError, ZSE};
+use crate::oracle::{MidPriceRates, RateSource};
+
+//every venue the traders know about, in the order they get registered
+fn venues() -> Vec<Venue> {
+    vec![
+        Venue {
+            name: "RCNZ",
+            lock_lifetime: 15,
+            max_locks: None,
+            new_with_quantities: |q, _, _| plain(RCNZ::new_with_quantities(q[0], q[2], q[1], q[3])),
+        },
+        Venue {
+            name: "BFB",
+            lock_lifetime: 10,
+            max_locks: None,
+            new_with_quantities: |q, _, _| plain(Bfb::new_with_quantities(q[0], q[2], q[1], q[3])),
+        },
+        Venue {
+            name: "BVC",
+            lock_lifetime: 12,
+            max_locks: Some(4),
+            new_with_quantities: |q, _, _| plain(BVCMarket::new_with_quantities(q[0], q[2], q[1], q[3])),
+        },
+        Venue {
+            name: "ZSE",
+            lock_lifetime: 15,
+            max_locks: Some(3),
+            new_with_quantities: |q, config, seed| zse(ZSE::with_config(q[0], q[2], q[1], q[3], config.clone(), seed)),
+        },
+    ]
+}
+
+//what a market can do besides the Market trait, same Rc as the market itself
+#[derive(Default)]
+struct Extensions {
+    cancel: Option<Rc<RefCell<dyn CancelLock>>>,
+    partial: Option<Rc<RefCell<dyn PartialFill>>>,
+    //locks with the typed trader errors and fills only the trader holding the token can make
+    owned: Option<Rc<RefCell<ZSE>>>,
+    //halts and resumes not picked up by `breaker_events` yet
+    breakers: Option<Receiver<BreakerEvent>>,
+}
+
+type Handles = (Rc<RefCell<dyn Market>>, Extensions);
+
+fn plain(market: Rc<RefCell<dyn Market>>) -> Handles {
+    (market, Extensions::default())
+}
+
+fn zse(market: Rc<RefCell<ZSE>>) -> Handles {
+    let extensions = Extensions {
+        cancel: Some(market.clone()),
+        partial: Some(market.clone()),
+        owned: Some(market.clone()),
+        breakers: Some(market.borrow_mut().subscribe_breakers()),
+    };
+    (market, extensions)
+}
+
+struct Venue {
+    name: &'static str,
+    lock_lifetime: i32,
+    max_locks: Option<usize>,
+    //quantities are always EUR USD YEN YUAN, the config and seed only reach our own market,
+    //the external markets keep their own randomness
+    new_with_quantities: fn(&[f32], &ZseConfig, u64) -> Handles,
+}
+
+pub struct MarketEntry {
+    market: Rc<RefCell<dyn Market>>,
+    name: String,
+    //what the market answers to get_name(), may differ from the display name (BFB -> "Baku stock exchange")
+    alias: &'static str,
+    lock_lifetime: i32,
+    max_locks: Option<usize>,
+    extensions: Extensions,
+}
+
+pub struct MarketRegistry {
+    entries: Vec<MarketEntry>,
+    bus: Rc<RefCell<EventBus>>,
+}
+
+impl MarketRegistry {
+    pub fn new() -> Self {
+        MarketRegistry {
+            entries: Vec::new(),
+            bus: EventBus::new(),
+        }
+    }
+
+    //quantities[i] holds EUR USD YEN YUAN for the i-th venue, zse configures our own market
+    //(e.g. with logging off for headless runs), one seed per venue is drawn from rng in registration order
+    pub fn new_with_quantities(quantities: &[Vec<f32>], zse: &ZseConfig, rng: &mut impl Rng) -> Self {
+        let mut res = Self::new();
+        for (v, q) in venues().into_iter().zip(quantities.iter()) {
+            let (market, extensions) = (v.new_with_quantities)(q, zse, rng.gen());
+            let i = res.register(market, v.name, v.lock_lifetime, v.max_locks);
+            res.entries[i].extensions = extensions;
+        }
+        res.subscribe_each_other();
+        res.connect_rates();
+        res
+    }
+
+    /// Goods for every venue drawn from `rng`, ready for `new_with_quantities`.
+    pub fn random_quantities(rng: &mut impl Rng) -> Vec<Vec<f32>> {
+        venues()
+            .iter()
+            .map(|_| {
+                let eur = ZSE::random_split(rng);
+                vec![
+                    eur[0],
+                    eur[1] * DEFAULT_EUR_USD_EXCHANGE_RATE,
+                    eur[2] * DEFAULT_EUR_YEN_EXCHANGE_RATE,
+                    eur[3] * DEFAULT_EUR_YUAN_EXCHANGE_RATE,
+                ]
+            })
+            .collect()
+    }
+
+    pub fn register(
+        &mut self,
+        market: Rc<RefCell<dyn Market>>,
+        name: &str,
+        lock_lifetime: i32,
+        max_locks: Option<usize>,
+    ) -> usize {
+        let alias = market.borrow().get_name();
+        self.entries.push(MarketEntry {
+            market,
+            name: name.to_string(),
+            alias,
+            lock_lifetime,
+            max_locks,
+            extensions: Extensions::default(),
+        });
+        self.entries.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<RefCell<dyn Market>>> {
+        self.entries.iter().map(|e| &e.market)
+    }
+
+    //accepts both the display name and the name the market reports
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|e| e.name == name || e.alias == name)
+    }
+
+    pub fn index_of_market(&self, market: &Rc<RefCell<dyn Market>>) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|e| Rc::ptr_eq(&e.market, market))
+    }
+
+    pub fn name(&self, i: usize) -> &str {
+        &self.entries[i].name
+    }
+
+    pub fn lock_lifetime(&self, i: usize) -> i32 {
+        self.entries[i].lock_lifetime
+    }
+
+    pub fn max_locks(&self, i: usize) -> Option<usize> {
+        self.entries[i].max_locks
+    }
+
+    //None if the market at i has no way to give a lock back
+    pub fn cancel_lock(&self, i: usize, token: &str, trader_name: &str) -> Option<Result<(), CancelLockError>> {
+        self.entries[i]
+            .extensions
+            .cancel
+            .as_ref()
+            .map(|c| c.borrow_mut().cancel_lock(token, trader_name))
+    }
+
+    //markets without the trader aware API give back their plain errors wrapped
+    pub fn lock_buy(&self, i: usize, kind: GoodKind, quantity: f32, bid: f32, trader_name: &str) -> Result<String, ZseTraderError> {
+        match &self.entries[i].extensions.owned {
+            Some(zse) => zse.borrow_mut().try_lock_buy(kind, quantity, bid, trader_name.to_string()),
+            None => self.entries[i].market.borrow_mut().lock_buy(kind, quantity, bid, trader_name.to_string()).map_err(ZseTraderError::LockBuy),
+        }
+    }
+
+    pub fn lock_sell(&self, i: usize, kind: GoodKind, quantity: f32, offer: f32, trader_name: &str) -> Result<String, ZseTraderError> {
+        match &self.entries[i].extensions.owned {
+            Some(zse) => zse.borrow_mut().try_lock_sell(kind, quantity, offer, trader_name.to_string()),
+            None => self.entries[i].market.borrow_mut().lock_sell(kind, quantity, offer, trader_name.to_string()).map_err(ZseTraderError::LockSell),
+        }
+    }
+
+    //full buy when cash covers the lock, otherwise whatever part of it the market lets us fill
+    pub fn buy_or_partial(&self, i: usize, trader_name: &str, token: String, cash: &mut Good) -> Result<Good, ZseTraderError> {
+        let extensions = &self.entries[i].extensions;
+        let res = match &extensions.owned {
+            Some(zse) => zse.borrow_mut().buy_as(trader_name, token.clone(), cash),
+            None => self.entries[i].market.borrow_mut().buy(token.clone(), cash).map_err(ZseTraderError::Buy),
+        };
+        //buy_as already refused a token that isn't ours, the partial fill can trust it
+        match (res, &extensions.partial) {
+            (Err(ZseTraderError::Buy(BuyError::InsufficientGoodQuantity { .. })), Some(partial)) => {
+                partial.borrow_mut().buy_partial(token, cash).map(|fill| fill.good).map_err(ZseTraderError::Buy)
+            }
+            (res, _) => res,
+        }
+    }
+
+    pub fn sell_or_partial(&self, i: usize, trader_name: &str, token: String, good: &mut Good) -> Result<Good, ZseTraderError> {
+        let extensions = &self.entries[i].extensions;
+        let res = match &extensions.owned {
+            Some(zse) => zse.borrow_mut().sell_as(trader_name, token.clone(), good),
+            None => self.entries[i].market.borrow_mut().sell(token.clone(), good).map_err(ZseTraderError::Sell),
+        };
+        match (res, &extensions.partial) {
+            (Err(ZseTraderError::Sell(SellError::InsufficientGoodQuantity { .. })), Some(partial)) => {
+                partial.borrow_mut().sell_partial(token, good).map(|fill| fill.good).map_err(ZseTraderError::Sell)
+            }
+            (res, _) => res,
+        }
+    }
+
+    /// Swaps our own market for the one saved at `path`, goods and all. Call it before anyone trades or
+    /// reads the markets, whatever held the old one keeps talking to it.
+    pub fn resume_zse(&mut self, path: &str) -> Result<(), SnapshotError> {
+        let i = match self.entries.iter().position(|e| e.extensions.owned.is_some()) {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        let (market, extensions) = zse(ZSE::load_snapshot(path)?);
+        EventBus::replace(&self.bus, &self.entries[i].name, market.clone());
+        self.entries[i].market = market;
+        self.entries[i].extensions = extensions;
+        self.connect_rates();
+        Ok(())
+    }
+
+    /// Saves our own market with `ZSE::save_snapshot`, None without one.
+    pub fn save_zse(&self, path: &str) -> Option<Result<(), SnapshotError>> {
+        Some(self.owned_zse()?.borrow_mut().save_snapshot(path))
+    }
+
+    /// What our own market counted since the last call, its stats start over afterwards. None without one.
+    pub fn take_zse_stats(&self) -> Option<ZseStats> {
+        let mut market = self.owned_zse()?.borrow_mut();
+        let stats = market.stats();
+        market.reset_stats();
+        Some(stats)
+    }
+
+    /// `ZSE::treasury_weights` of our own market, None without one.
+    pub fn zse_treasury(&self) -> Option<[f32; 4]> {
+        Some(self.owned_zse()?.borrow().treasury_weights())
+    }
+
+    fn owned_zse(&self) -> Option<&Rc<RefCell<ZSE>>> {
+        self.entries.iter().find_map(|e| e.extensions.owned.as_ref())
+    }
+
+    //halts and resumes since the last call, with the index of the market they happened at
+    pub fn breaker_events(&self) -> Vec<(usize, BreakerEvent)> {
+        let mut res = Vec::new();
+        for (i, e) in self.entries.iter().enumerate() {
+            if let Some(rx) = &e.extensions.breakers {
+                res.extend(rx.try_iter().map(|event| (i, event)));
+            }
+        }
+        res
+    }
+
+    //a ZSE valuing goods at `RateSource::MidPrice` asks every other market of the registry
+    fn connect_rates(&self) {
+        for (i, e) in self.entries.iter().enumerate() {
+            let zse = match &e.extensions.owned {
+                Some(zse) if zse.borrow().rate_source() == RateSource::MidPrice => zse,
+                _ => continue,
+            };
+            let others = self.entries.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, o)| o.market.clone()).collect();
+            zse.borrow_mut().set_rates(Box::new(MidPriceRates::new(others)));
+        }
+    }
+
+    //markets hear about each other through the bus, never directly
+    pub fn subscribe_each_other(&self) {
+        for e in &self.entries {
+            EventBus::connect(&self.bus, &e.name, e.market.clone());
+        }
+    }
+
+    pub fn wait_one_day(&self) {
+        EventBus::wait_one_day(&self.bus);
+    }
+
+    /// Market days every registered market went through.
+    pub fn day(&self) -> u32 {
+        self.bus.borrow().days()
+    }
+
+    pub fn bus(&self) -> &Rc<RefCell<EventBus>> {
+        &self.bus
+    }
+}
+
+impl Index<usize> for MarketRegistry {
+    type Output = Rc<RefCell<dyn Market>>;
+
+    fn index(&self, i: usize) -> &Self::Output {
+        &self.entries[i].market
+    }
+}
@@ -0,0 +1,662 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+
+use unitn_market_2022::good::good_kind::GoodKind;
+
+/// One line (or the init block) of a `log_ZSE.txt` file.
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    /// the `ZSE#n` instance that wrote the line, `None` for logs older than instance ids
+    pub market: Option<u32>,
+    pub timestamp: String,
+    pub event: LogEvent,
+}
+
+/// Failed operations carry the error variant when the log has it, older logs only say `ERROR`.
+#[derive(Debug, Clone)]
+pub enum LogEvent {
+    /// EUR USD YEN YUAN
+    Init([f32; 4]),
+    LockBuy {
+        trader: String,
+        kind: GoodKind,
+        qty: f32,
+        bid: f32,
+        outcome: Result<String, Option<String>>,
+    },
+    LockSell {
+        trader: String,
+        kind: GoodKind,
+        qty: f32,
+        offer: f32,
+        outcome: Result<String, Option<String>>,
+    },
+    /// fee is the part of the price that went to the market's fee account
+    Buy {
+        token: String,
+        fee: f32,
+        outcome: Result<(), Option<String>>,
+    },
+    /// fee is what the market paid on top of the price into its fee account
+    Sell {
+        token: String,
+        fee: f32,
+        outcome: Result<(), Option<String>>,
+    },
+    Cancel {
+        token: String,
+        outcome: Result<(), Option<String>>,
+    },
+    /// the market dropped a lock nobody used in time
+    Expire { token: String },
+    /// part of a buy lock executed, the rest stays locked
+    PartialBuy {
+        token: String,
+        qty: f32,
+        price: f32,
+        fee: f32,
+        outcome: Result<(), Option<String>>,
+    },
+    PartialSell {
+        token: String,
+        qty: f32,
+        price: f32,
+        fee: f32,
+        outcome: Result<(), Option<String>>,
+    },
+    /// the market converted part of one good into another, cost in EUR
+    Rebalance {
+        from: GoodKind,
+        sold: f32,
+        to: GoodKind,
+        bought: f32,
+        cost: f32,
+    },
+    /// a circuit breaker stopped new locks, reason as `HaltReason::encode` writes it
+    Halt { reason: String, days: u32 },
+    Resume,
+    Note(String),
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(String),
+    Malformed { line: usize, text: String },
+    UnterminatedInit { line: usize },
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "cannot read log: {}", e),
+            ParseError::Malformed { line, text } => write!(f, "line {}: cannot parse `{}`", line, text),
+            ParseError::UnterminatedInit { line } => write!(f, "line {}: MARKET INITIALIZATION never ends", line),
+        }
+    }
+}
+
+pub fn parse_log(reader: impl BufRead) -> Result<Vec<LogEntry>, ParseError> {
+    let mut entries = Vec::new();
+    //market, timestamp, first line, quantities so far
+    let mut init: Option<(Option<u32>, String, usize, [f32; 4])> = None;
+
+    for (n, line) in reader.lines().enumerate() {
+        let line_number = n + 1;
+        let line = line.map_err(|e| ParseError::Io(e.to_string()))?;
+        let malformed = || ParseError::Malformed { line: line_number, text: line.clone() };
+
+        if let Some((market, timestamp, start, mut goods)) = init.take() {
+            let content = line.trim();
+            if content == "END MARKET INITIALIZATION" {
+                entries.push(LogEntry { market, timestamp, event: LogEvent::Init(goods) });
+                continue;
+            }
+            let (name, value) = content.split_once(':').ok_or_else(malformed)?;
+            let index = match name.trim() {
+                "EUR" => 0,
+                "USD" => 1,
+                "YEN" => 2,
+                "YUAN" => 3,
+                _ => return Err(malformed()),
+            };
+            goods[index] = value.trim().parse().map_err(|_| malformed())?;
+            init = Some((market, timestamp, start, goods));
+            continue;
+        }
+
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut split = line.splitn(3, '|');
+        let market = match split.next().ok_or_else(malformed)? {
+            "ZSE" => None,
+            prefix => Some(prefix.strip_prefix("ZSE#").and_then(|n| n.parse().ok()).ok_or_else(malformed)?),
+        };
+        let timestamp = split.next().ok_or_else(malformed)?.to_string();
+        let body = split.next().ok_or_else(malformed)?;
+
+        if body.trim() == "MARKET INITIALIZATION" {
+            init = Some((market, timestamp, line_number, [0.0; 4]));
+            continue;
+        }
+        let event = parse_body(body).ok_or_else(malformed)?;
+        entries.push(LogEntry { market, timestamp, event });
+    }
+
+    if let Some((_, _, start, _)) = init {
+        return Err(ParseError::UnterminatedInit { line: start });
+    }
+    Ok(entries)
+}
+
+fn parse_body(body: &str) -> Option<LogEvent> {
+    if let Some(rest) = body.strip_prefix("LOCK_BUY-") {
+        let (trader, kind, qty, bid, outcome) = parse_lock(rest, "KIND_TO_BUY", "QUANTITY_TO_BUY", "BID")?;
+        return Some(LogEvent::LockBuy { trader, kind, qty, bid, outcome });
+    }
+    if let Some(rest) = body.strip_prefix("LOCK_SELL-") {
+        let (trader, kind, qty, offer, outcome) = parse_lock(rest, "KIND_TO_SELL", "QUANTITY_TO_SELL", "OFFER")?;
+        return Some(LogEvent::LockSell { trader, kind, qty, offer, outcome });
+    }
+    if let Some(rest) = body.strip_prefix("BUY-TOKEN:") {
+        let (token, outcome) = parse_execution(rest)?;
+        let (token, fee) = split_fee(&token)?;
+        return Some(LogEvent::Buy { token, fee, outcome });
+    }
+    if let Some(rest) = body.strip_prefix("SELL-TOKEN:") {
+        let (token, outcome) = parse_execution(rest)?;
+        let (token, fee) = split_fee(&token)?;
+        return Some(LogEvent::Sell { token, fee, outcome });
+    }
+    if let Some(rest) = body.strip_prefix("CANCEL-TOKEN:") {
+        let (token, outcome) = parse_execution(rest)?;
+        return Some(LogEvent::Cancel { token, outcome });
+    }
+    if let Some(rest) = body.strip_prefix("PARTIAL_BUY-TOKEN:") {
+        let (token, qty, price, outcome) = parse_partial(rest)?;
+        let (token, fee) = split_fee(&token)?;
+        return Some(LogEvent::PartialBuy { token, qty, price, fee, outcome });
+    }
+    if let Some(rest) = body.strip_prefix("PARTIAL_SELL-TOKEN:") {
+        let (token, qty, price, outcome) = parse_partial(rest)?;
+        let (token, fee) = split_fee(&token)?;
+        return Some(LogEvent::PartialSell { token, qty, price, fee, outcome });
+    }
+    if let Some(rest) = body.strip_prefix("EXPIRE-TOKEN:") {
+        let (token, _) = rest.split_once("-KIND:")?;
+        return Some(LogEvent::Expire { token: token.to_string() });
+    }
+    if let Some(rest) = body.strip_prefix("HALT-REASON:") {
+        let (reason, days) = rest.rsplit_once("-DAYS:")?;
+        return Some(LogEvent::Halt { reason: reason.to_string(), days: days.parse().ok()? });
+    }
+    if body == "RESUME" {
+        return Some(LogEvent::Resume);
+    }
+    if let Some(rest) = body.strip_prefix("REBALANCE-FROM:") {
+        let (from, rest) = rest.split_once("-SOLD:")?;
+        let (sold, rest) = rest.split_once("-TO:")?;
+        let (to, rest) = rest.split_once("-BOUGHT:")?;
+        let (bought, cost) = rest.split_once("-COST:")?;
+        return Some(LogEvent::Rebalance {
+            from: parse_kind(from)?,
+            sold: sold.parse().ok()?,
+            to: parse_kind(to)?,
+            bought: bought.parse().ok()?,
+            cost: cost.parse().ok()?,
+        });
+    }
+    Some(LogEvent::Note(body.to_string()))
+}
+
+//trader names and negative numbers may contain '-', so we cut on the field markers instead
+fn parse_lock(rest: &str, kind_key: &str, qty_key: &str, price_key: &str) -> Option<(String, GoodKind, f32, f32, Result<String, Option<String>>)> {
+    let (trader, rest) = rest.split_once(&format!("-{}:", kind_key))?;
+    let (kind, rest) = rest.split_once(&format!("-{}:", qty_key))?;
+    let (qty, rest) = rest.split_once(&format!("-{}:", price_key))?;
+
+    let (price, outcome) = if let Some((price, token)) = rest.split_once("-TOKEN:") {
+        (price, Ok(token.to_string()))
+    } else {
+        let at = rest.rfind("-ERROR")?;
+        let error = rest[at + "-ERROR".len()..].strip_prefix(':').map(|v| v.to_string());
+        (&rest[..at], Err(error))
+    };
+
+    Some((trader.to_string(), parse_kind(kind)?, qty.parse().ok()?, price.parse().ok()?, outcome))
+}
+
+fn parse_execution(rest: &str) -> Option<(String, Result<(), Option<String>>)> {
+    if let Some(token) = rest.strip_suffix("-OK") {
+        return Some((token.to_string(), Ok(())));
+    }
+    let at = rest.rfind("-ERROR")?;
+    let error = rest[at + "-ERROR".len()..].strip_prefix(':').map(|v| v.to_string());
+    Some((rest[..at].to_string(), Err(error)))
+}
+
+//executions that charged a fee log their token as `TOKEN-FEE:x`
+fn split_fee(token: &str) -> Option<(String, f32)> {
+    match token.split_once("-FEE:") {
+        Some((token, fee)) => Some((token.to_string(), fee.parse().ok()?)),
+        None => Some((token.to_string(), 0.0)),
+    }
+}
+
+fn parse_partial(rest: &str) -> Option<(String, f32, f32, Result<(), Option<String>>)> {
+    let (token, rest) = rest.split_once("-QUANTITY:")?;
+    let (qty, rest) = rest.split_once("-PRICE:")?;
+    let (price, outcome) = parse_execution(rest)?;
+    Some((token.to_string(), qty.parse().ok()?, price.parse().ok()?, outcome))
+}
+
+fn parse_kind(kind: &str) -> Option<GoodKind> {
+    match kind {
+        "EUR" => Some(GoodKind::EUR),
+        "USD" => Some(GoodKind::USD),
+        "YEN" => Some(GoodKind::YEN),
+        "YUAN" => Some(GoodKind::YUAN),
+        _ => None,
+    }
+}
+
+fn index_of(kind: &GoodKind) -> usize {
+    match *kind {
+        GoodKind::EUR => 0,
+        GoodKind::USD => 1,
+        GoodKind::YEN => 2,
+        GoodKind::YUAN => 3,
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct TraderActivity {
+    pub lock_buy: u32,
+    pub lock_sell: u32,
+    pub buy: u32,
+    pub sell: u32,
+    pub cancel: u32,
+    /// partial executions, buy and sell
+    pub partial: u32,
+    /// locks the market dropped because they were never used
+    pub expired: u32,
+    pub failed: u32,
+    /// EUR paid to the market
+    pub spent: f32,
+    /// EUR received from the market
+    pub earned: f32,
+}
+
+/// Everything `replay` rebuilds from a log.
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// inventory of each market after every init and every executed trade or rebalance, EUR USD YEN YUAN
+    pub timeline: Vec<(Option<u32>, String, [f32; 4])>,
+    /// quantity of each good traders bought from the market
+    pub bought: [f32; 4],
+    /// quantity of each good traders sold to the market
+    pub sold: [f32; 4],
+    /// attempts per operation
+    pub attempts: HashMap<&'static str, u32>,
+    /// failures per (operation, error variant)
+    pub failures: HashMap<(&'static str, String), u32>,
+    pub traders: HashMap<String, TraderActivity>,
+    /// EUR the market moved into its fee account
+    pub fees: f32,
+    /// internal conversions and the EUR they cost
+    pub rebalances: u32,
+    pub rebalance_cost: f32,
+    /// circuit breaker halts, with their reasons
+    pub halts: Vec<String>,
+    /// buy/sell lines whose token never showed up in a lock line
+    pub orphan_tokens: u32,
+}
+
+//what one market instance looks like at a point of the log
+#[derive(Default)]
+struct MarketState {
+    goods: [f32; 4],
+    //token -> (trader, good index, qty, price)
+    locks: HashMap<String, (String, usize, f32, f32)>,
+}
+
+/// Markets sharing a log are replayed apart, by the instance in their lines.
+pub fn replay(entries: &[LogEntry]) -> ReplayReport {
+    let mut report = ReplayReport::default();
+    let mut markets: HashMap<Option<u32>, MarketState> = HashMap::new();
+
+    for entry in entries {
+        let MarketState { goods, locks } = markets.entry(entry.market).or_default();
+        match &entry.event {
+            LogEvent::Init(q) => {
+                *goods = *q;
+                locks.clear();
+                report.timeline.push((entry.market, entry.timestamp.clone(), *goods));
+            }
+            LogEvent::LockBuy { trader, kind, qty, bid, outcome } => {
+                report.count("LOCK_BUY", outcome.as_ref().err());
+                let activity = report.traders.entry(trader.clone()).or_default();
+                match outcome {
+                    Ok(token) => {
+                        activity.lock_buy += 1;
+                        locks.insert(token.clone(), (trader.clone(), index_of(kind), *qty, *bid));
+                    }
+                    Err(_) => activity.failed += 1,
+                }
+            }
+            LogEvent::LockSell { trader, kind, qty, offer, outcome } => {
+                report.count("LOCK_SELL", outcome.as_ref().err());
+                let activity = report.traders.entry(trader.clone()).or_default();
+                match outcome {
+                    Ok(token) => {
+                        activity.lock_sell += 1;
+                        locks.insert(token.clone(), (trader.clone(), index_of(kind), *qty, *offer));
+                    }
+                    Err(_) => activity.failed += 1,
+                }
+            }
+            LogEvent::Buy { token, fee, outcome } => {
+                report.count("BUY", outcome.as_ref().err());
+                let (trader, index, qty, price) = match locks.get(token) {
+                    Some(lock) => lock.clone(),
+                    None => {
+                        report.orphan_tokens += 1;
+                        continue;
+                    }
+                };
+                let activity = report.traders.entry(trader).or_default();
+                if outcome.is_err() {
+                    activity.failed += 1;
+                    continue;
+                }
+                activity.buy += 1;
+                activity.spent += price;
+                goods[index] -= qty;
+                goods[0] += price - fee;
+                report.fees += fee;
+                report.bought[index] += qty;
+                locks.remove(token);
+                report.timeline.push((entry.market, entry.timestamp.clone(), *goods));
+            }
+            LogEvent::Sell { token, fee, outcome } => {
+                report.count("SELL", outcome.as_ref().err());
+                let (trader, index, qty, price) = match locks.get(token) {
+                    Some(lock) => lock.clone(),
+                    None => {
+                        report.orphan_tokens += 1;
+                        continue;
+                    }
+                };
+                let activity = report.traders.entry(trader).or_default();
+                if outcome.is_err() {
+                    activity.failed += 1;
+                    continue;
+                }
+                activity.sell += 1;
+                activity.earned += price;
+                goods[index] += qty;
+                goods[0] -= price + fee;
+                report.fees += fee;
+                report.sold[index] += qty;
+                locks.remove(token);
+                report.timeline.push((entry.market, entry.timestamp.clone(), *goods));
+            }
+            LogEvent::Cancel { token, outcome } => {
+                report.count("CANCEL", outcome.as_ref().err());
+                let trader = match locks.get(token) {
+                    Some((trader, _, _, _)) => trader.clone(),
+                    None => {
+                        report.orphan_tokens += 1;
+                        continue;
+                    }
+                };
+                let activity = report.traders.entry(trader).or_default();
+                if outcome.is_err() {
+                    activity.failed += 1;
+                    continue;
+                }
+                activity.cancel += 1;
+                locks.remove(token);
+            }
+            LogEvent::PartialBuy { token, qty, price, fee, outcome } | LogEvent::PartialSell { token, qty, price, fee, outcome } => {
+                let buying = matches!(entry.event, LogEvent::PartialBuy { .. });
+                report.count(if buying { "PARTIAL_BUY" } else { "PARTIAL_SELL" }, outcome.as_ref().err());
+                let (trader, index) = match locks.get_mut(token) {
+                    Some(lock) => {
+                        if outcome.is_ok() {
+                            lock.2 -= qty;
+                            lock.3 -= price;
+                        }
+                        (lock.0.clone(), lock.1)
+                    }
+                    None => {
+                        report.orphan_tokens += 1;
+                        continue;
+                    }
+                };
+                let activity = report.traders.entry(trader).or_default();
+                if outcome.is_err() {
+                    activity.failed += 1;
+                    continue;
+                }
+                activity.partial += 1;
+                report.fees += fee;
+                if buying {
+                    activity.spent += price;
+                    goods[index] -= qty;
+                    goods[0] += price - fee;
+                    report.bought[index] += qty;
+                } else {
+                    activity.earned += price;
+                    goods[index] += qty;
+                    goods[0] -= price + fee;
+                    report.sold[index] += qty;
+                }
+                report.timeline.push((entry.market, entry.timestamp.clone(), *goods));
+            }
+            LogEvent::Expire { token } => {
+                *report.attempts.entry("EXPIRE").or_insert(0) += 1;
+                match locks.remove(token) {
+                    Some((trader, _, _, _)) => report.traders.entry(trader).or_default().expired += 1,
+                    None => report.orphan_tokens += 1,
+                }
+            }
+            LogEvent::Rebalance { from, sold, to, bought, cost } => {
+                report.rebalances += 1;
+                report.rebalance_cost += cost;
+                goods[index_of(from)] -= sold;
+                goods[index_of(to)] += bought;
+                report.timeline.push((entry.market, entry.timestamp.clone(), *goods));
+            }
+            LogEvent::Halt { reason, .. } => report.halts.push(reason.clone()),
+            LogEvent::Resume | LogEvent::Note(_) => {}
+        }
+    }
+
+    report
+}
+
+impl ReplayReport {
+    fn count(&mut self, op: &'static str, error: Option<&Option<String>>) {
+        *self.attempts.entry(op).or_insert(0) += 1;
+        if let Some(error) = error {
+            let variant = error.clone().unwrap_or_else(|| "Unknown".to_string());
+            *self.failures.entry((op, variant)).or_insert(0) += 1;
+        }
+    }
+
+    pub fn print(&self) {
+        let names = ["EUR", "USD", "YEN", "YUAN"];
+
+        println!("Inventory snapshots: {}", self.timeline.len());
+        let mut markets: Vec<_> = self.timeline.iter().map(|(market, _, _)| *market).collect();
+        markets.sort();
+        markets.dedup();
+        for market in markets {
+            let mut snapshots = self.timeline.iter().filter(|(m, _, _)| *m == market);
+            let first = snapshots.next().map(|(_, _, g)| *g).unwrap_or_default();
+            let last = snapshots.last().map(|(_, _, g)| *g).unwrap_or(first);
+            println!("{}\tstart\t\tend", market_name(market));
+            for i in 0..4 {
+                println!("{}:\t{}\t{}", names[i], first[i], last[i]);
+            }
+        }
+
+        println!("\nVolumes\tbought\t\tsold");
+        for i in 0..4 {
+            println!("{}:\t{}\t{}", names[i], self.bought[i], self.sold[i]);
+        }
+
+        if self.fees > 0.0 {
+            println!("\nFees collected: {} EUR", self.fees);
+        }
+        if !self.halts.is_empty() {
+            println!("\nHalts: {}", self.halts.len());
+            for reason in &self.halts {
+                println!("\t{}", reason);
+            }
+        }
+        if self.rebalances > 0 {
+            println!("\nRebalances: {}, costing {} EUR", self.rebalances, self.rebalance_cost);
+        }
+
+        println!("\nFailure rates:");
+        let mut ops: Vec<_> = self.attempts.iter().collect();
+        ops.sort();
+        for (op, attempts) in ops {
+            let failed: u32 = self.failures.iter().filter(|((o, _), _)| o == op).map(|(_, n)| n).sum();
+            println!("{}: {}/{} failed ({:.1}%)", op, failed, attempts, 100.0 * failed as f32 / *attempts as f32);
+            let mut errors: Vec<_> = self.failures.iter().filter(|((o, _), _)| o == op).collect();
+            errors.sort_by(|a, b| b.1.cmp(a.1));
+            for ((_, variant), n) in errors {
+                println!("\t{}: {}", variant, n);
+            }
+        }
+
+        println!("\nTraders:");
+        let mut traders: Vec<_> = self.traders.iter().collect();
+        traders.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, a) in traders {
+            println!(
+                "{}: lock_buy {} lock_sell {} buy {} sell {} cancel {} partial {} expired {} failed {} spent {} earned {}",
+                name, a.lock_buy, a.lock_sell, a.buy, a.sell, a.cancel, a.partial, a.expired, a.failed, a.spent, a.earned
+            );
+        }
+        if self.orphan_tokens > 0 {
+            println!("\n{} executions refer to tokens that were never locked in this log", self.orphan_tokens);
+        }
+    }
+
+    pub fn write_timeline(&self, path: &str) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        writeln!(file, "market,timestamp,eur,usd,yen,yuan")?;
+        for (market, ts, g) in &self.timeline {
+            writeln!(file, "{},{},{},{},{},{}", market_name(*market), ts, g[0], g[1], g[2], g[3])?;
+        }
+        Ok(())
+    }
+}
+
+//old logs have no instance, their lines all belong to one market
+fn market_name(market: Option<u32>) -> String {
+    match market {
+        Some(n) => format!("ZSE#{}", n),
+        None => "ZSE".to_string(),
+    }
+}
+
+/// Entry point of the `replay` subcommand, errors are for the caller to report.
+pub fn run(path: &str, timeline: Option<String>) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| format!("Cannot open {}: {}", path, e))?;
+    let entries = parse_log(BufReader::new(file)).map_err(|e| e.to_string())?;
+
+    let report = replay(&entries);
+    report.print();
+    if let Some(timeline) = timeline {
+        report.write_timeline(&timeline).map_err(|e| format!("Cannot write {}: {}", timeline, e))?;
+        println!("\nTimeline written to {}", timeline);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use unitn_market_2022::good::good_kind::GoodKind;
+
+    use super::{parse_log, replay, LogEntry, LogEvent, ParseError};
+
+    fn parse(text: &str) -> Result<Vec<LogEntry>, ParseError> {
+        parse_log(text.as_bytes())
+    }
+
+    const INIT: &str = "|2022:11:20:10:00:00:000|MARKET INITIALIZATION \n EUR: +1e4 \n USD: +2e3 \n YEN: +3e5 \n YUAN: +4e1 \n END MARKET INITIALIZATION\n";
+
+    #[test]
+    fn parses_the_init_block_with_and_without_instance() {
+        let text = format!("ZSE{}ZSE#7{}", INIT, INIT);
+        let entries = parse(&text).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].market, None);
+        assert_eq!(entries[1].market, Some(7));
+        for entry in &entries {
+            assert_eq!(entry.timestamp, "2022:11:20:10:00:00:000");
+            assert!(matches!(entry.event, LogEvent::Init(q) if q == [1e4, 2e3, 3e5, 40.0]));
+        }
+    }
+
+    #[test]
+    fn failures_keep_their_variant_when_the_log_has_it() {
+        let text = "ZSE#1|t|LOCK_BUY-a-b-KIND_TO_BUY:USD-QUANTITY_TO_BUY:1-BID:-2-ERROR:BidTooLow\n\
+                    ZSE|t|LOCK_SELL-a-KIND_TO_SELL:YEN-QUANTITY_TO_SELL:1-OFFER:2-ERROR\n\
+                    ZSE#1|t|BUY-TOKEN:abc-ERROR:ExpiredToken\n\
+                    ZSE#1|t|PARTIAL_SELL-TOKEN:abc-QUANTITY:1-PRICE:2-ERROR:UnrecognizedToken\n";
+        let entries = parse(text).unwrap();
+
+        match &entries[0].event {
+            LogEvent::LockBuy { trader, kind, bid, outcome, .. } => {
+                assert_eq!(trader, "a-b");
+                assert!(matches!(kind, GoodKind::USD));
+                assert_eq!(*bid, -2.0);
+                assert_eq!(outcome, &Err(Some("BidTooLow".to_string())));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(&entries[1].event, LogEvent::LockSell { outcome: Err(None), .. }));
+        assert!(matches!(&entries[2].event, LogEvent::Buy { outcome: Err(Some(e)), .. } if e == "ExpiredToken"));
+        assert!(matches!(&entries[3].event, LogEvent::PartialSell { outcome: Err(Some(e)), .. } if e == "UnrecognizedToken"));
+    }
+
+    #[test]
+    fn malformed_lines_are_reported_with_their_number() {
+        let cases = [
+            "ZSE|t|RESUME\nMARKET|t|RESUME",
+            "ZSE#one|t|RESUME",
+            "ZSE|t",
+            "ZSE|t|LOCK_BUY-a-KIND_TO_BUY:GBP-QUANTITY_TO_BUY:1-BID:2-TOKEN:x",
+            "ZSE|t|MARKET INITIALIZATION \n EUR: ten \n END MARKET INITIALIZATION",
+        ];
+        for (text, line) in cases.iter().zip([2, 1, 1, 1, 2]) {
+            assert!(matches!(parse(text), Err(ParseError::Malformed { line: l, .. }) if l == line), "{}", text);
+        }
+        assert!(matches!(parse("ZSE|t|RESUME\nZSE|t|MARKET INITIALIZATION \n EUR: 1"), Err(ParseError::UnterminatedInit { line: 2 })));
+    }
+
+    #[test]
+    fn markets_sharing_a_log_are_replayed_apart() {
+        let text = format!(
+            "ZSE#1{}ZSE#1|t|LOCK_BUY-a-KIND_TO_BUY:USD-QUANTITY_TO_BUY:100-BID:90-TOKEN:x\nZSE#2{}ZSE#1|t|BUY-TOKEN:x-OK\nZSE#2|t|BUY-TOKEN:x-OK\n",
+            INIT, INIT
+        );
+        let report = replay(&parse(&text).unwrap());
+
+        //market 2 never locked `x`, its buy is the only orphan
+        assert_eq!(report.orphan_tokens, 1);
+        assert_eq!(report.traders["a"].buy, 1);
+        let (market, _, goods) = report.timeline.last().unwrap();
+        assert_eq!(*market, Some(1));
+        assert_eq!(*goods, [1e4 + 90.0, 2e3 - 100.0, 3e5, 40.0]);
+        assert!(report.timeline.iter().any(|(m, _, g)| *m == Some(2) && *g == [1e4, 2e3, 3e5, 40.0]));
+    }
+}
@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::Write;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::backtest::{backtest, headless};
+use crate::market::ZseConfig;
+use crate::registry::MarketRegistry;
+use crate::trader_core::{strategy_by_name, Trader, STRATEGIES};
+
+//one strategy on one seed, goods are what new_with_quantities gets
+struct Job {
+    seed: u64,
+    strategy: &'static str,
+    split: Vec<f32>,
+    values: Vec<Vec<f32>>,
+    //same for every strategy on a seed, so they see the same tokens
+    trader_seed: u64,
+}
+
+pub struct RunResult {
+    pub seed: u64,
+    pub strategy: &'static str,
+    pub start: f32,
+    pub end: f32,
+    pub max_drawdown: f32,
+    pub fill_rate: f32,
+}
+
+/// Final budgets of one strategy over every seed.
+pub struct Standing {
+    pub strategy: &'static str,
+    pub runs: usize,
+    pub mean: f32,
+    pub median: f32,
+    pub stdev: f32,
+}
+
+/// Runs every strategy in `STRATEGIES` on the same goods for seeds first..first + seeds, `threads` runs at a time.
+pub fn tournament(first: u64, seeds: u64, days: u32, budget: f32, threads: usize, zse: &ZseConfig) -> Vec<RunResult> {
+    //the markets hold Rc's, so only their goods cross threads and each run rebuilds its own
+    let mut jobs = Vec::new();
+    for seed in first..first + seeds {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let values = MarketRegistry::random_quantities(&mut rng);
+        let split = crate::split_budget(budget, &mut rng);
+        let trader_seed = rng.gen();
+        for strategy in STRATEGIES {
+            jobs.push(Job { seed, strategy, split: split.clone(), values: values.clone(), trader_seed });
+        }
+    }
+    let jobs = Arc::new(Mutex::new(jobs));
+    let zse = headless(zse);
+
+    let (tx, rx) = mpsc::channel();
+    let mut workers = Vec::new();
+    for _ in 0..threads.max(1) {
+        let jobs = jobs.clone();
+        let tx = tx.clone();
+        let zse = zse.clone();
+        workers.push(thread::spawn(move || loop {
+            let job = match jobs.lock().unwrap().pop() {
+                Some(job) => job,
+                None => break,
+            };
+            let strategy = strategy_by_name(job.strategy).unwrap();
+            let mut trader = Trader::new_with_quantities(strategy, 1, 0, job.split, job.values, &zse, job.trader_seed);
+            let report = backtest(&mut trader, job.strategy, days);
+            let result = RunResult {
+                seed: job.seed,
+                strategy: job.strategy,
+                start: report.start,
+                end: report.end(),
+                max_drawdown: report.max_drawdown().1,
+                fill_rate: report.counts.fill_rate(),
+            };
+            tx.send(result).unwrap();
+        }));
+    }
+    drop(tx);
+
+    let mut results: Vec<RunResult> = rx.iter().collect();
+    for w in workers {
+        let _ = w.join();
+    }
+    results.sort_by(|a, b| a.seed.cmp(&b.seed).then(a.strategy.cmp(b.strategy)));
+    results
+}
+
+/// Best mean final budget first.
+pub fn standings(results: &[RunResult]) -> Vec<Standing> {
+    let mut res = Vec::new();
+    for strategy in STRATEGIES {
+        let mut ends: Vec<f32> = results.iter().filter(|r| r.strategy == strategy).map(|r| r.end).collect();
+        if ends.is_empty() {
+            continue;
+        }
+        ends.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let n = ends.len();
+        let mean = ends.iter().sum::<f32>() / n as f32;
+        let median = if n % 2 == 0 { (ends[n / 2 - 1] + ends[n / 2]) / 2.0 } else { ends[n / 2] };
+        let variance = ends.iter().map(|e| (e - mean) * (e - mean)).sum::<f32>() / n as f32;
+        res.push(Standing { strategy, runs: n, mean, median, stdev: variance.sqrt() });
+    }
+    res.sort_by(|a, b| b.mean.partial_cmp(&a.mean).unwrap_or(std::cmp::Ordering::Equal));
+    res
+}
+
+pub fn write_csv(results: &[RunResult], path: &str) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "seed,strategy,start,end,pnl,max_drawdown,fill_rate")?;
+    for r in results {
+        writeln!(file, "{},{},{},{},{},{},{}", r.seed, r.strategy, r.start, r.end, r.end - r.start, r.max_drawdown, r.fill_rate)?;
+    }
+    Ok(())
+}
+
+//seeds start at `seed`, 0 when missing
+pub fn run(seeds: u64, days: u32, budget: f32, csv: &str, threads: Option<usize>, seed: Option<u64>, zse: &ZseConfig) {
+    let threads = threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    let results = tournament(seed.unwrap_or(0), seeds, days, budget, threads, zse);
+
+    println!("{} seeds, {} market days each\n", seeds, days);
+    println!("#\tstrategy\truns\tmean\t\tmedian\t\tstdev");
+    for (i, s) in standings(&results).iter().enumerate() {
+        println!("{}\t{}\t\t{}\t{:.2}\t{:.2}\t{:.2}", i + 1, s.strategy, s.runs, s.mean, s.median, s.stdev);
+    }
+    match write_csv(&results, csv) {
+        Ok(_) => println!("\nRuns written to {}", csv),
+        Err(e) => println!("\nCannot write {}: {}", csv, e),
+    }
+}
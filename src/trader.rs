@@ -1,32 +1,26 @@
 use std::cell::RefCell;
 use std::fmt::{Debug, Display};
 use std::rc::Rc;
-use std::sync::mpsc::Sender;
 
-use bfb::bfb_market::Bfb;
+use rand::rngs::StdRng;
 use rand::Rng;
-use rcnz_market::rcnz::RCNZ;
-use unitn_market_2022::good::consts::{
-    DEFAULT_EUR_USD_EXCHANGE_RATE, DEFAULT_EUR_YEN_EXCHANGE_RATE, DEFAULT_EUR_YUAN_EXCHANGE_RATE,
-};
-use unitn_market_2022::good::{good::Good, good_kind::GoodKind};
-use unitn_market_2022::market::{LockBuyError, LockSellError, Market, MarketGetterError};
-use unitn_market_2022::{subscribe_each_other, wait_one_day};
-use BVC::BVCMarket;
-use clap::Parser;
+use unitn_market_2022::good::good_kind::GoodKind;
+use unitn_market_2022::market::{Market, MarketGetterError};
+
+use crate::market::ZseTraderError;
+use crate::trader_core::{get_goodkind_by_index, get_index_by_goodkind, Side, TraderCore, TraderStrategy};
 
 const STARTING_CAPITAL: f32 = 40000.0;
 const NUM_LOCK: i32 = 3;
 
-unsafe impl Send for ZSE_Trader {} //mandatory in order to pass tx to the trader DONT TOUCH --needed by the compiler
-pub struct ZSE_Trader {
-    name: String,
-    markets: Vec<Rc<RefCell<dyn Market>>>,
+/// Buys and sells random goods, picking the cheapest, dearest and middle market in turn.
+pub struct ThreeM {
+    //buy and sell rate of each good, one row per market, sized at the first tick
     prices: Vec<Vec<Vec<f32>>>,
-    goods: Vec<Good>,
     token_buy: Vec<Locking>,
     token_sell: Vec<Locking>,
     information: Data,
+    count: i32,
 }
 
 #[derive(Debug, Clone)]
@@ -82,13 +76,13 @@ impl Value {
     fn new_max() -> Self {
         Value {
             val: 0.0,
-            market: 3,
+            market: 0,
         }
     }
     fn new_min() -> Self {
         Value {
             val: STARTING_CAPITAL,
-            market: 3,
+            market: 0,
         }
     }
 }
@@ -103,65 +97,22 @@ impl PartialOrd for Value {
     }
 }
 
-impl ZSE_Trader {
-    fn default() -> Self {
-        let name = "ZSE_Trader".to_string();
-        let markets = Vec::new();
-        let prices = vec![vec![vec![0.0; 4]; 3]; 2];
-        let goods = vec![];
-        let token_buy = Vec::new();
-        let token_sell = Vec::new();
-        let information = Data::new();
-        Self {
-            name,
-            markets,
-            prices,
-            goods,
-            token_buy,
-            token_sell,
-            information,
-        }
-    }
+impl ThreeM {
     pub fn new() -> Self {
-        let mut res = Self::default();
-        res.markets.push(RCNZ::new_random());
-        res.markets.push(Bfb::new_random());
-        res.markets.push(BVCMarket::new_random());
-        subscribe_each_other!(res.markets[0], res.markets[1], res.markets[2]);
-        res.goods = vec![
-            Good::new(GoodKind::EUR, STARTING_CAPITAL),
-            Good::new(GoodKind::USD, 0.0),
-            Good::new(GoodKind::YEN, 0.0),
-            Good::new(GoodKind::YUAN, 0.0),
-        ];
-        res
-    }
-    pub fn new_with_quantities(data: Vec<f32>, m1: Vec<f32>, m2: Vec<f32>, m3: Vec<f32>) -> Self {
-        let mut res = Self::default();
-        res.markets
-            .push(RCNZ::new_with_quantities(m1[0], m1[1], m1[2], m1[3]));
-        res.markets
-            .push(Bfb::new_with_quantities(m2[0], m2[1], m2[2], m2[3]));
-        res.markets
-            .push(BVCMarket::new_with_quantities(m3[0], m3[1], m3[2], m3[3]));
-        subscribe_each_other!(res.markets[0], res.markets[1], res.markets[2]);
-
-        res.goods = vec![
-            Good::new(GoodKind::EUR, data[0]),
-            Good::new(GoodKind::USD, data[1] * DEFAULT_EUR_USD_EXCHANGE_RATE),
-            Good::new(GoodKind::YEN, data[2] * DEFAULT_EUR_YEN_EXCHANGE_RATE),
-            Good::new(GoodKind::YUAN, data[3] * DEFAULT_EUR_YUAN_EXCHANGE_RATE),
-        ];
-        res
-    }
-
-    pub fn get_name(&self) -> &String {
-        &self.name
+        ThreeM {
+            prices: Vec::new(),
+            token_buy: Vec::new(),
+            token_sell: Vec::new(),
+            information: Data::new(),
+            count: 0,
+        }
     }
 
-    pub fn update_all_prices(&mut self) {
-        for m in &self.markets {
-            let index = get_index_by_market(m.borrow_mut().get_name());
+    pub fn update_all_prices(&mut self, core: &TraderCore) {
+        if self.prices.is_empty() {
+            self.prices = vec![vec![vec![0.0; 4]; core.markets.len()]; 2];
+        }
+        for (index, m) in core.markets.iter().enumerate() {
             let goods = m.borrow_mut().get_goods();
             for g in goods {
                 let index_kind = get_index_by_goodkind(&g.good_kind);
@@ -171,7 +122,7 @@ impl ZSE_Trader {
         }
     }
 
-    pub fn print_prices(&self) {
+    pub fn print_prices(&self, core: &TraderCore) {
         for i in 0..self.prices.len() {
             if i == 0 {
                 println!("\nBuy prices:");
@@ -180,7 +131,7 @@ impl ZSE_Trader {
             }
             println!("\tEUR\tUSD\t\tYEN\t\tYUAN");
             for j in 0..self.prices[i].len() {
-                let name = get_name_market(j);
+                let name = core.markets.name(j);
                 print!("{}:\t", name);
                 for k in 0..self.prices[i][j].len() {
                     print!("{}\t", self.prices[i][j][k]);
@@ -191,8 +142,8 @@ impl ZSE_Trader {
         }
     }
 
-    pub fn print_goods_trader(&self) {
-        for g in &self.goods {
+    pub fn print_goods_trader(&self, core: &TraderCore) {
+        for g in &core.goods {
             println!("{:?} ", g);
         }
         println!();
@@ -202,121 +153,99 @@ impl ZSE_Trader {
         println!("{:?}", self.information);
     }
 
-    pub fn get_qty_good_trader(&mut self, i: usize) -> f32 {
-        self.goods[i].get_qty()
-    }
-
-    pub fn trade(&mut self, tx: &Sender<String>) {
-        let mut count = 0;
-        let mut state = true;
-        while state {
-            state = self.strategy(count, tx);
-            count += 1;
-        }
-        //self.print_goods_trader();
-        //self.print_data();
-        //println!("tot cicli: {}", count);
-    }
-
-    pub fn strategy(&mut self, x: i32, tx: &Sender<String>) -> bool {
+    pub fn strategy(&mut self, core: &mut TraderCore, x: i32) -> bool {
         let mut lock: bool;
         let mut done = 0;
-        if self.get_qty_good_trader(0) > 800.0 {
+        if core.get_qty(0) > 800.0 {
             //BUY
-            let index_gk_buy = rand::thread_rng().gen_range(0..18) % 3 + 1;
-            let gk_buy = get_goodkind_by_index(&index_gk_buy);
+            let index_gk_buy = core.rng.gen_range(0..18) % 3 + 1;
+            let gk_buy = get_goodkind_by_index(index_gk_buy);
             let mut count_lock_buy = 0;
 
-            let want_buy = self.chose(x, index_gk_buy, Mode::Buy);
-            let mb = &self.markets[want_buy.market].clone();
-            let qty_to_buy = self.generate_qty(mb, gk_buy, Mode::Buy);
+            let want_buy = self.chose(&mut core.rng, x, index_gk_buy, Mode::Buy);
+            let mb = &core.markets[want_buy.market].clone();
+            let qty_to_buy = self.generate_qty(core, mb, gk_buy, Mode::Buy);
 
-            if mb.borrow_mut().get_name() == "BVC" {
-                for i in self.token_buy.iter() {
-                    if i.market.borrow_mut().get_name() == "BVC" {
-                        count_lock_buy += 1;
-                    }
+            for i in self.token_buy.iter() {
+                if Rc::ptr_eq(&i.market, mb) {
+                    count_lock_buy += 1;
                 }
             }
-            if count_lock_buy < 4 {
-                lock = self.try_lock_buy(mb, gk_buy, qty_to_buy);
+            if core.markets.max_locks(want_buy.market).map_or(true, |max| count_lock_buy < max) {
+                lock = self.try_lock_buy(core, want_buy.market, gk_buy, qty_to_buy);
             } else { lock = false; }
 
             if lock {
                 self.information.lock_buy += 1;
                 //println!("want to buy: {} -> {}", gk_buy, mb.borrow_mut().get_name());
             } else {
-                wait_one_day!(self.markets[0], self.markets[1], self.markets[2]);
+                core.wait_one_day();
                 self.information.wait += 1;
                 //println!("\nWAITING LOCK-BUY\n");
             }
             self.update_time();
-            self.update_all_prices();
+            self.update_all_prices(core);
 
             if self.information.lock_buy % NUM_LOCK == 2 {
                 while !self.token_buy.is_empty() {
-                    if self.try_buy() {
+                    if self.try_buy(core) {
                         self.information.buy += 1;
-                        write_metadata(&self.goods, tx);
                     } else {
-                        wait_one_day!(self.markets[0], self.markets[1], self.markets[2]);
+                        core.wait_one_day();
                         self.information.wait += 1;
                         //println!("\nWAITING BUY\n");
                     }
                     self.update_time();
-                    self.update_all_prices();
+                    self.update_all_prices(core);
                 }
             }
             done = 1;
         }
 
-        if self.get_qty_good_trader(1) > 200.0
-            || self.get_qty_good_trader(2) > 200.0
-            || self.get_qty_good_trader(3) > 200.0
+        if core.get_qty(1) > 200.0
+            || core.get_qty(2) > 200.0
+            || core.get_qty(3) > 200.0
         {
             //SELL
-            let index_gk_sell = rand::thread_rng().gen_range(0..18) % 3 + 1;
-            let gk_sell = get_goodkind_by_index(&index_gk_sell);
+            let index_gk_sell = core.rng.gen_range(0..18) % 3 + 1;
+            let gk_sell = get_goodkind_by_index(index_gk_sell);
             let mut count_lock_sell = 0;
 
-            let want_sell = self.chose(x, index_gk_sell, Mode::Sell);
-            let ms = &self.markets[want_sell.market].clone();
-            let qty_sell = self.generate_qty(ms, gk_sell, Mode::Sell);
+            let want_sell = self.chose(&mut core.rng, x, index_gk_sell, Mode::Sell);
+            let ms = &core.markets[want_sell.market].clone();
+            let qty_sell = self.generate_qty(core, ms, gk_sell, Mode::Sell);
 
-            if ms.borrow_mut().get_name() == "BVC" {
-                for i in self.token_sell.iter() {
-                    if i.to_owned().market.borrow_mut().get_name() == "BVC" {
-                        count_lock_sell += 1;
-                    }
+            for i in self.token_sell.iter() {
+                if Rc::ptr_eq(&i.market, ms) {
+                    count_lock_sell += 1;
                 }
             }
-            if count_lock_sell < 4 {
-                lock = self.try_lock_sell(ms, gk_sell, qty_sell);
+            if core.markets.max_locks(want_sell.market).map_or(true, |max| count_lock_sell < max) {
+                lock = self.try_lock_sell(core, want_sell.market, gk_sell, qty_sell);
             } else { lock = false; }
 
             if lock {
                 self.information.lock_sell += 1;
                 //println!("want to sell: {} of {} to {}", qty_sell, gk_sell, ms.borrow_mut().get_name());
             } else {
-                wait_one_day!(self.markets[0], self.markets[1], self.markets[2]);
+                core.wait_one_day();
                 self.information.wait += 1;
                 //println!("\nWAITING LOCK-SELL\n");
             }
             self.update_time();
-            self.update_all_prices();
+            self.update_all_prices(core);
 
             if self.information.lock_sell > 0 && self.information.lock_sell % (NUM_LOCK - 1) == 0 {
                 while !self.token_sell.is_empty() {
-                    if self.try_sell() {
+                    if self.try_sell(core) {
                         self.information.sell += 1;
-                        write_metadata(&self.goods, tx);
                     } else {
-                        wait_one_day!(self.markets[0], self.markets[1], self.markets[2]);
+                        core.wait_one_day();
                         self.information.wait += 1;
                         //println!("\nWAITING SELL\n");
                     }
                     self.update_time();
-                    self.update_all_prices();
+                    self.update_all_prices(core);
                 }
             }
             done = 2;
@@ -324,7 +253,7 @@ impl ZSE_Trader {
         done == 1 || done == 2
     }
 
-    fn find_min_price(&mut self, mode: Mode, gk: usize) -> Value {
+    fn find_min_price(&mut self, rng: &mut StdRng, mode: Mode, gk: usize) -> Value {
         let mut price_market: Value = Value::new_min();
         let x = match mode {
             Mode::Buy => 0,
@@ -335,7 +264,7 @@ impl ZSE_Trader {
                 price_market.val = self.prices[x][i][gk];
                 price_market.market = i;
             } else if price_market.val == self.prices[x][i][gk] {
-                let num = rand::thread_rng().gen_range(0..100);
+                let num = rng.gen_range(0..100);
                 if num % 2 == 0 {
                     price_market.val = self.prices[x][i][gk];
                     price_market.market = i;
@@ -345,7 +274,7 @@ impl ZSE_Trader {
         price_market
     }
 
-    fn find_max_price(&mut self, mode: Mode, gk: usize) -> Value {
+    fn find_max_price(&mut self, rng: &mut StdRng, mode: Mode, gk: usize) -> Value {
         let mut price_market: Value = Value::new_max();
         let x = match mode {
             Mode::Buy => 0,
@@ -356,7 +285,7 @@ impl ZSE_Trader {
                 price_market.val = self.prices[x][i][gk];
                 price_market.market = i;
             } else if price_market.val == self.prices[x][i][gk] {
-                let num = rand::thread_rng().gen_range(0..100);
+                let num = rng.gen_range(0..100);
                 if num % 2 == 1 {
                     price_market.val = self.prices[x][i][gk];
                     price_market.market = i;
@@ -366,11 +295,11 @@ impl ZSE_Trader {
         price_market
     }
 
-    fn find_mid_price(&mut self, mode: Mode, gk: usize) -> Value {
+    fn find_mid_price(&mut self, rng: &mut StdRng, mode: Mode, gk: usize) -> Value {
         let mut price_market: Value = Value::new_max();
-        let min = self.find_min_price(mode.clone(), gk).market;
-        let max = self.find_max_price(mode.clone(), gk).market;
-        let mut v: Vec<usize> = vec![0, 1, 2];
+        let min = self.find_min_price(rng, mode.clone(), gk).market;
+        let max = self.find_max_price(rng, mode.clone(), gk).market;
+        let mut v: Vec<usize> = (0..self.prices[0].len()).collect();
         v.retain(|&x| x != min && x != max);
 
         let x = match mode {
@@ -383,18 +312,19 @@ impl ZSE_Trader {
         price_market
     }
 
-    fn chose(&mut self, count: i32, index: usize, mode: Mode) -> Value {
+    fn chose(&mut self, rng: &mut StdRng, count: i32, index: usize, mode: Mode) -> Value {
         if count % 3 == 0 {
-            self.find_min_price(mode, index)
+            self.find_min_price(rng, mode, index)
         } else if count % 3 == 1 {
-            self.find_max_price(mode, index)
+            self.find_max_price(rng, mode, index)
         } else {
-            self.find_mid_price(mode, index)
+            self.find_mid_price(rng, mode, index)
         }
     }
 
-    fn try_lock_buy(&mut self, market: &Rc<RefCell<dyn Market>>, gk: GoodKind, qty: f32) -> bool {
-        let string: Result<String, LockBuyError>; //token
+    fn try_lock_buy(&mut self, core: &mut TraderCore, index: usize, gk: GoodKind, qty: f32) -> bool {
+        let market = &core.markets[index].clone();
+        let string: Result<String, ZseTraderError>; //token
         let offer: f32;
         let min_bid_offer: Result<f32, MarketGetterError>;
         let final_val: (f32, f32);
@@ -403,14 +333,12 @@ impl ZSE_Trader {
             min_bid_offer = market.borrow_mut().get_buy_price(gk, qty);
             if min_bid_offer.is_ok() {
                 offer = min_bid_offer.clone().unwrap() + 0.8293;
-                if offer <= 0.0 || offer > self.goods[0].get_qty() { return false; }
+                if offer <= 0.0 || offer > core.goods[0].get_qty() { return false; }
                 //prevent InsufficientGoodQuantity - buy + seeing that i want to do 2 lock and buy in the future
 
-                final_val = self.check_good_qty(gk, offer);
+                final_val = self.check_good_qty(core, gk, offer);
                 if final_val.0 < 0.0 { return false; }
-                string = market
-                    .borrow_mut()
-                    .lock_buy(gk, qty, offer, self.get_name().clone());
+                string = core.lock_buy(index, gk, qty, offer);
                 if let Ok(token) = string {
                     let new_qty_euro = final_val.0 - offer; //how much EUR i have after lock (NOT change yet)
                     let new_qty_gk_buy = final_val.1 + qty;
@@ -430,31 +358,33 @@ impl ZSE_Trader {
         false
     }
 
-    fn try_buy(&mut self) -> bool {
+    fn try_buy(&mut self, core: &mut TraderCore) -> bool {
         //index of token_buy is always = 0 -> trader buys goods in order of locking -> once a good is buying, the relative token is removed
         let mut result = false;
         if self.token_buy[0].time < 10 {
             let token = self.token_buy[0].token.clone();
             let market = self.token_buy[0].market.clone();
             let gk = self.token_buy[0].kind;
-            let qty = self.token_buy[0].qty;
-
-            let buy = market.borrow_mut().buy(token, &mut self.goods[0]);
-            match buy {
-                Ok(_) => {
-                    let _ = self.goods[get_index_by_goodkind(&gk)].merge(Good::new(gk, qty));
-                    //println!("buy {} with {} -> {}\t", gk, market.borrow_mut().get_name(), qty);
-                    result = true;
-                },
-                Err(_) => result = false,
+
+            //the core takes a partial fill when float drift leaves us a bit short of the locked price
+            if let Some(i) = core.markets.index_of_market(&market) {
+                result = core.buy(i, token, gk);
+            }
+        } else {
+            //too old to trust, hand the lock back so the market can reuse it
+            let stale = self.token_buy[0].market.clone();
+            if let Some(i) = core.markets.index_of_market(&stale) {
+                core.cancel_lock(i, &self.token_buy[0].token);
+                core.expired(Side::Buy, i, &self.token_buy[0].token);
             }
         }
         self.token_buy.remove(0);
         result
     }
 
-    fn try_lock_sell(&mut self, market: &Rc<RefCell<dyn Market>>, gk: GoodKind, qty: f32) -> bool {
-        let string: Result<String, LockSellError>; //token
+    fn try_lock_sell(&mut self, core: &mut TraderCore, index: usize, gk: GoodKind, qty: f32) -> bool {
+        let market = &core.markets[index].clone();
+        let string: Result<String, ZseTraderError>; //token
         let min_offer: Result<f32, MarketGetterError>;
         let offer: f32;
         let final_val: (f32, f32);
@@ -473,12 +403,10 @@ impl ZSE_Trader {
                         return false;
                     }
                 }
-                final_val = self.check_good_qty(gk, offer);
+                final_val = self.check_good_qty(core, gk, offer);
                 if final_val.0 < 0.0 { return false; }
 
-                string = market
-                    .borrow_mut()
-                    .lock_sell(gk, qty, offer, self.get_name().clone());
+                string = core.lock_sell(index, gk, qty, offer);
                 if let Ok(token) = string {
                     let new_qty_euro = final_val.0 + offer;
                     let new_qty_gk_sell = final_val.1 - qty;
@@ -498,31 +426,29 @@ impl ZSE_Trader {
         false
     }
 
-    fn try_sell(&mut self) -> bool {
+    fn try_sell(&mut self, core: &mut TraderCore) -> bool {
         let mut result = false;
         if self.token_sell[0].time < 10 {
             let token = self.token_sell[0].token.clone();
             let market = self.token_sell[0].market.clone();
             let gk = self.token_sell[0].kind;
-            let qty = self.token_sell[0].qty;
-
-            let sell = market
-                .borrow_mut()
-                .sell(token, &mut self.goods[get_index_by_goodkind(&gk)]);
-            match sell {
-                Ok(_) => {
-                    let _ = self.goods[0].merge(Good::new(GoodKind::EUR, qty));
-                    //println!("sell {} with {} -> {}\t", gk, market.borrow_mut().get_name(), qty);
-                    result = true;
-                },
-                Err(_) => result = false,
+
+            if let Some(i) = core.markets.index_of_market(&market) {
+                result = core.sell(i, token, gk);
+            }
+        } else {
+            //too old to trust, hand the lock back so the market can reuse it
+            let stale = self.token_sell[0].market.clone();
+            if let Some(i) = core.markets.index_of_market(&stale) {
+                core.cancel_lock(i, &self.token_sell[0].token);
+                core.expired(Side::Sell, i, &self.token_sell[0].token);
             }
         }
         self.token_sell.remove(0);
         result
     }
 
-    fn generate_qty(&mut self, market: &Rc<RefCell<dyn Market>>, gk: GoodKind, mode: Mode) -> f32 {
+    fn generate_qty(&mut self, core: &mut TraderCore, market: &Rc<RefCell<dyn Market>>, gk: GoodKind, mode: Mode) -> f32 {
         let mut max = 200.0;
         let min = 5.0;
         let mut qty: f32;
@@ -530,14 +456,14 @@ impl ZSE_Trader {
             Mode::Buy => 0, //arbitrary
             Mode::Sell => {
                 //sell a random qty of gk that i'm sure trader posses
-                max = self.goods[get_index_by_goodkind(&gk)].get_qty()
-                    - (self.goods[get_index_by_goodkind(&gk)].get_qty() * 0.3);
+                max = core.goods[get_index_by_goodkind(&gk)].get_qty()
+                    - (core.goods[get_index_by_goodkind(&gk)].get_qty() * 0.3);
                 1
             }
         };
         if max < min { return 0.0; }
         else {
-            qty = rand::thread_rng().gen_range(min..get_max(max, 200.0));
+            qty = core.rng.gen_range(min..get_max(max, 200.0));
         }
         if x == 0 {
             let check = market.borrow_mut().get_goods();
@@ -550,9 +476,9 @@ impl ZSE_Trader {
         qty
     }
 
-    fn check_good_qty(&mut self, kind: GoodKind, offer: f32) -> (f32, f32){
-        let mut final_eur = self.goods[0].get_qty();
-        let mut final_gk = self.goods[get_index_by_goodkind(&kind)].get_qty();
+    fn check_good_qty(&mut self, core: &TraderCore, kind: GoodKind, offer: f32) -> (f32, f32){
+        let mut final_eur = core.goods[0].get_qty();
+        let mut final_gk = core.goods[get_index_by_goodkind(&kind)].get_qty();
         if !self.token_buy.is_empty() {
             if !self.token_sell.is_empty() {
                 if self.token_buy[self.token_buy.len() - 1].time
@@ -563,7 +489,7 @@ impl ZSE_Trader {
                     }
                     final_eur = self.token_sell[self.token_sell.len() - 1].new_qty_euro;
                     if kind == self.token_sell[self.token_sell.len() - 1].kind {
-                        final_gk = self.goods[get_index_by_goodkind(&kind)].get_qty();
+                        final_gk = core.goods[get_index_by_goodkind(&kind)].get_qty();
                     }
                 } else {
                     if offer > self.token_buy[self.token_buy.len() - 1].new_qty_euro {
@@ -571,16 +497,16 @@ impl ZSE_Trader {
                     }
                     final_eur = self.token_buy[self.token_buy.len() - 1].new_qty_euro;
                     if kind == self.token_buy[self.token_buy.len() - 1].kind {
-                        final_gk = self.goods[get_index_by_goodkind(&kind)].get_qty();
+                        final_gk = core.goods[get_index_by_goodkind(&kind)].get_qty();
                     }
                 }
             } else {
                 final_eur = self.token_buy[self.token_buy.len() - 1].new_qty_euro;
-                final_gk = self.goods[get_index_by_goodkind(&kind)].get_qty();
+                final_gk = core.goods[get_index_by_goodkind(&kind)].get_qty();
             }
         } else if !self.token_sell.is_empty() {
             final_eur = self.token_sell[self.token_sell.len() - 1].new_qty_euro;
-            final_gk = self.goods[get_index_by_goodkind(&kind)].get_qty();
+            final_gk = core.goods[get_index_by_goodkind(&kind)].get_qty();
         }
         (final_eur, final_gk)
     }
@@ -600,60 +526,17 @@ fn get_max(a: f32, b: f32) -> f32 {
     else { b }
 }
 
-fn get_index_by_market(m: &str) -> usize {
-    match m {
-        "RCNZ" => 0,
-        "Baku stock exchange" => 1,
-        "BFB" => 1,
-        "BVC" => 2,
-        _ => panic!("Market not found"),
+impl TraderStrategy for ThreeM {
+    fn name(&self) -> &'static str {
+        "3M"
     }
-}
-
-fn get_name_market(n: usize) -> String {
-    let name = match n {
-        0 => "RCNZ".to_string(),
-        1 => "BFB".to_string(),
-        2 => "BVC".to_string(),
-        _ => panic!("Error in print_prices"),
-    };
-    name
-}
 
-fn get_index_by_goodkind(kind: &GoodKind) -> usize {
-    match *kind {
-        GoodKind::EUR => 0,
-        GoodKind::USD => 1,
-        GoodKind::YEN => 2,
-        GoodKind::YUAN => 3,
-    }
-}
-
-fn get_goodkind_by_index(i: &usize) -> GoodKind {
-    match *i {
-        0 => GoodKind::EUR,
-        1 => GoodKind::USD,
-        2 => GoodKind::YEN,
-        _ => GoodKind::YUAN,
-    }
-}
-
-fn convert_to_eur(good: &Good) -> f32 {
-    match good.get_kind() {
-        GoodKind::EUR => good.get_qty(),
-        GoodKind::USD => good.get_qty() / DEFAULT_EUR_USD_EXCHANGE_RATE,
-        GoodKind::YEN => good.get_qty() / DEFAULT_EUR_YEN_EXCHANGE_RATE,
-        GoodKind::YUAN => good.get_qty() / DEFAULT_EUR_YUAN_EXCHANGE_RATE,
+    fn on_tick(&mut self, core: &mut TraderCore) -> bool {
+        if self.prices.is_empty() {
+            self.update_all_prices(core);
+        }
+        let state = self.strategy(core, self.count);
+        self.count += 1;
+        state
     }
 }
-
-fn write_metadata(goods: &Vec<Good>, tx: &Sender<String>) {
-    let args = crate::Args::parse();
-    let mut s = "2 ".to_string();
-    for g in goods {
-        s.push_str(&format!("{} ", convert_to_eur(g)));
-    }
-    s.push('\n');
-    tx.send(s).unwrap();
-    std::thread::sleep(std::time::Duration::from_millis(args.delay));
-}
\ No newline at end of file
@@ -0,0 +1,443 @@
+use std::fmt::Debug;
+use std::sync::mpsc::Sender;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use unitn_market_2022::good::{good::Good, good_kind::GoodKind};
+use unitn_market_2022::market::{BuyError, LockBuyError, LockSellError, SellError};
+
+use crate::market::{variant_name, BreakerEvent, HaltReason, ZseConfig, ZseTraderError};
+use crate::oracle::{MidPriceRates, RateOracle};
+use crate::registry::MarketRegistry;
+use crate::trader::ThreeM;
+use crate::trader_balordo::Dropship;
+
+/// Names `strategy_by_name` knows, in the order `main` lists them.
+pub const STRATEGIES: [&str; 2] = ["dropship", "3M"];
+
+pub fn strategy_by_name(name: &str) -> Option<Box<dyn TraderStrategy>> {
+    match name {
+        "dropship" => Some(Box::new(Dropship::new())),
+        "3M" => Some(Box::new(ThreeM::new())),
+        _ => None,
+    }
+}
+
+/// Decision logic of a trader, everything else lives in `TraderCore`.
+pub trait TraderStrategy {
+    /// What the strategy calls itself at the markets.
+    fn name(&self) -> &'static str;
+
+    /// One step, false once the strategy is done trading.
+    fn on_tick(&mut self, core: &mut TraderCore) -> bool;
+
+    /// Called after the tick for every buy or sell that went through.
+    fn on_fill(&mut self, _core: &mut TraderCore, _fill: &TraderFill) {}
+
+    /// Called after the tick for every lock, buy or sell a market refused.
+    fn on_error(&mut self, _core: &mut TraderCore, _error: &TraderError) {}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// A buy or sell that went through, `received` is what we got: the good for a buy, EUR for a sell.
+#[derive(Clone, Debug)]
+pub struct TraderFill {
+    pub side: Side,
+    pub market: usize,
+    pub kind: GoodKind,
+    pub received: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TradeOp {
+    LockBuy,
+    LockSell,
+    Buy,
+    Sell,
+}
+
+/// What a trader tells its sinks, `day` is the market days it waited so far.
+/// Portfolio values are EUR USD YEN YUAN, each valued in EUR.
+#[derive(Clone, Debug)]
+pub enum TraderEvent {
+    PortfolioUpdate { trader: u32, day: u32, values: [f32; 4] },
+    LockPlaced { trader: u32, day: u32, side: Side, market: usize, kind: GoodKind, quantity: f32, price: f32 },
+    LockFailed { trader: u32, day: u32, side: Side, market: usize, kind: GoodKind, error: Refusal },
+    Filled { trader: u32, day: u32, fill: TraderFill },
+    /// a buy or sell on a lock we hold was refused
+    FillFailed { trader: u32, day: u32, side: Side, market: usize, kind: GoodKind, error: Refusal },
+    /// the strategy gave up on a lock it never executed
+    Expired { trader: u32, day: u32, side: Side, market: usize, token: String },
+    /// a market stopped taking locks, see `BreakerEvent`
+    MarketHalted { trader: u32, day: u32, market: usize, reason: HaltReason, days: u32 },
+    MarketResumed { trader: u32, day: u32, market: usize },
+    Bankrupt { trader: u32, day: u32 },
+    /// the strategy stopped with money left
+    Finished { trader: u32, day: u32, budget: f32 },
+}
+
+impl TraderEvent {
+    pub fn trader(&self) -> u32 {
+        match self {
+            TraderEvent::PortfolioUpdate { trader, .. }
+            | TraderEvent::LockPlaced { trader, .. }
+            | TraderEvent::LockFailed { trader, .. }
+            | TraderEvent::Filled { trader, .. }
+            | TraderEvent::FillFailed { trader, .. }
+            | TraderEvent::Expired { trader, .. }
+            | TraderEvent::MarketHalted { trader, .. }
+            | TraderEvent::MarketResumed { trader, .. }
+            | TraderEvent::Bankrupt { trader, .. }
+            | TraderEvent::Finished { trader, .. } => *trader,
+        }
+    }
+}
+
+/// Why a market refused a lock, buy or sell: `ZseTraderError` cut down to something sinks on other threads can keep.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Refusal {
+    /// bid below the lowest the market takes, or offer above the highest
+    Price { asked: f32, acceptable: f32 },
+    /// not enough of the good, at the market for a lock or with us for a fill
+    Quantity { requested: f32, available: f32 },
+    MaxLocksReached,
+    UnknownToken { token: String },
+    ExpiredToken { token: String },
+    NotTokenOwner { token: String },
+    /// one of the ZSE per-trader limits, as `ZseTraderError` describes it
+    TraderLimit(String),
+    Halted(HaltReason),
+    /// any other market error, by variant name
+    Other(String),
+}
+
+impl From<&ZseTraderError> for Refusal {
+    fn from(error: &ZseTraderError) -> Self {
+        match error {
+            ZseTraderError::LockBuy(LockBuyError::BidTooLow { low_bid, lowest_acceptable_bid, .. }) => Refusal::Price { asked: *low_bid, acceptable: *lowest_acceptable_bid },
+            ZseTraderError::LockSell(LockSellError::OfferTooHigh { high_offer, highest_acceptable_offer, .. }) => Refusal::Price { asked: *high_offer, acceptable: *highest_acceptable_offer },
+            ZseTraderError::LockBuy(LockBuyError::InsufficientGoodQuantityAvailable { requested_good_quantity, available_good_quantity, .. }) => {
+                Refusal::Quantity { requested: *requested_good_quantity, available: *available_good_quantity }
+            }
+            ZseTraderError::LockSell(LockSellError::InsufficientDefaultGoodQuantityAvailable { offered_good_quantity, available_good_quantity, .. }) => {
+                Refusal::Quantity { requested: *offered_good_quantity, available: *available_good_quantity }
+            }
+            ZseTraderError::Buy(BuyError::InsufficientGoodQuantity { contained_quantity, pre_agreed_quantity })
+            | ZseTraderError::Sell(SellError::InsufficientGoodQuantity { contained_quantity, pre_agreed_quantity }) => Refusal::Quantity { requested: *pre_agreed_quantity, available: *contained_quantity },
+            ZseTraderError::LockBuy(LockBuyError::MaxAllowedLocksReached) | ZseTraderError::LockSell(LockSellError::MaxAllowedLocksReached) => Refusal::MaxLocksReached,
+            ZseTraderError::Buy(BuyError::UnrecognizedToken { unrecognized_token }) | ZseTraderError::Sell(SellError::UnrecognizedToken { unrecognized_token }) => {
+                Refusal::UnknownToken { token: unrecognized_token.clone() }
+            }
+            ZseTraderError::Buy(BuyError::ExpiredToken { expired_token }) | ZseTraderError::Sell(SellError::ExpiredToken { expired_token }) => Refusal::ExpiredToken { token: expired_token.clone() },
+            ZseTraderError::NotTokenOwner { token, .. } => Refusal::NotTokenOwner { token: token.clone() },
+            ZseTraderError::TooManyOpenLocks { .. }
+            | ZseTraderError::TooManyLocksOnGood { .. }
+            | ZseTraderError::DailyNotionalExceeded { .. }
+            | ZseTraderError::InventoryShareExceeded { .. } => Refusal::TraderLimit(error.to_string()),
+            ZseTraderError::Halted { reason } => Refusal::Halted(reason.clone()),
+            ZseTraderError::LockBuy(e) => Refusal::Other(variant_name(e)),
+            ZseTraderError::LockSell(e) => Refusal::Other(variant_name(e)),
+            ZseTraderError::Buy(e) => Refusal::Other(variant_name(e)),
+            ZseTraderError::Sell(e) => Refusal::Other(variant_name(e)),
+        }
+    }
+}
+
+/// What a market answered when it refused us, `error` is the error as `{:?}` prints it.
+#[derive(Clone, Debug)]
+pub struct TraderError {
+    pub op: TradeOp,
+    pub market: usize,
+    pub error: String,
+}
+
+/// What the core did for its strategy, same fields as the 3M `Data` plus refusals.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TradeCounts {
+    pub lock_buy: u32,
+    pub lock_sell: u32,
+    pub buy: u32,
+    pub sell: u32,
+    pub wait: u32,
+    pub refused: u32,
+}
+
+impl TradeCounts {
+    /// Share of locks that ended in a buy or sell, 0 before the first lock.
+    pub fn fill_rate(&self) -> f32 {
+        let locks = self.lock_buy + self.lock_sell;
+        if locks == 0 {
+            return 0.0;
+        }
+        (self.buy + self.sell) as f32 / locks as f32
+    }
+}
+
+/// Portfolio, market handles and reporting channel every strategy shares.
+pub struct TraderCore {
+    name: String,
+    //sent with every event, tells the visualizer which plot it goes to
+    id: u32,
+    pub markets: MarketRegistry,
+    /// EUR USD YEN YUAN
+    pub goods: Vec<Good>,
+    //values our goods, mid price of the markets we trade on
+    rates: Box<dyn RateOracle>,
+    /// every random choice a strategy makes comes from here
+    pub rng: StdRng,
+    tx: Option<Sender<TraderEvent>>,
+    delay_ms: u64,
+    fills: Vec<TraderFill>,
+    errors: Vec<TraderError>,
+    counts: TradeCounts,
+}
+
+/// A strategy plugged into its core.
+pub struct Trader {
+    core: TraderCore,
+    strategy: Box<dyn TraderStrategy>,
+}
+
+unsafe impl Send for Trader {} //mandatory in order to pass tx to the trader DONT TOUCH --needed by the compiler
+
+impl TraderCore {
+    //data is in EUR, turned into each good at the rate the markets quote right now
+    fn new(name: &str, id: u32, markets: MarketRegistry, data: &[f32], delay_ms: u64, rng: StdRng) -> Self {
+        let rates: Box<dyn RateOracle> = Box::new(MidPriceRates::new(markets.iter().cloned().collect()));
+        let goods = vec![
+            Good::new(GoodKind::EUR, data[0]),
+            Good::new(GoodKind::USD, rates.from_eur(GoodKind::USD, data[1])),
+            Good::new(GoodKind::YEN, rates.from_eur(GoodKind::YEN, data[2])),
+            Good::new(GoodKind::YUAN, rates.from_eur(GoodKind::YUAN, data[3])),
+        ];
+        TraderCore {
+            name: name.to_string(),
+            id,
+            markets,
+            goods,
+            rates,
+            rng,
+            tx: None,
+            delay_ms,
+            fills: Vec::new(),
+            errors: Vec::new(),
+            counts: TradeCounts::default(),
+        }
+    }
+
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn get_budget(&self) -> f32 {
+        self.goods.iter().map(|g| self.rates.to_eur(g)).sum()
+    }
+
+    /// EUR value of each good we hold, EUR USD YEN YUAN.
+    pub fn holdings_eur(&self) -> Vec<f32> {
+        self.goods.iter().map(|g| self.rates.to_eur(g)).collect()
+    }
+
+    pub fn counts(&self) -> TradeCounts {
+        self.counts
+    }
+
+    /// Market days gone by on our markets' day clock.
+    pub fn day(&self) -> u32 {
+        self.markets.day()
+    }
+
+    pub fn get_qty(&self, i: usize) -> f32 {
+        self.goods[i].get_qty()
+    }
+
+    pub fn wait_one_day(&mut self) {
+        self.markets.wait_one_day();
+        self.counts.wait += 1;
+    }
+
+    pub fn lock_buy(&mut self, market: usize, kind: GoodKind, quantity: f32, bid: f32) -> Result<String, ZseTraderError> {
+        let res = self.markets.lock_buy(market, kind, quantity, bid, &self.name);
+        match &res {
+            Ok(_) => {
+                self.counts.lock_buy += 1;
+                self.send(TraderEvent::LockPlaced { trader: self.id, day: self.day(), side: Side::Buy, market, kind, quantity, price: bid });
+            }
+            Err(error) => {
+                self.refused(TradeOp::LockBuy, market, error);
+                self.send(TraderEvent::LockFailed { trader: self.id, day: self.day(), side: Side::Buy, market, kind, error: error.into() });
+            }
+        }
+        res
+    }
+
+    pub fn lock_sell(&mut self, market: usize, kind: GoodKind, quantity: f32, offer: f32) -> Result<String, ZseTraderError> {
+        let res = self.markets.lock_sell(market, kind, quantity, offer, &self.name);
+        match &res {
+            Ok(_) => {
+                self.counts.lock_sell += 1;
+                self.send(TraderEvent::LockPlaced { trader: self.id, day: self.day(), side: Side::Sell, market, kind, quantity, price: offer });
+            }
+            Err(error) => {
+                self.refused(TradeOp::LockSell, market, error);
+                self.send(TraderEvent::LockFailed { trader: self.id, day: self.day(), side: Side::Sell, market, kind, error: error.into() });
+            }
+        }
+        res
+    }
+
+    /// Pays a buy lock with our EUR, taking a partial fill where the market has one, and reports the new portfolio.
+    pub fn buy(&mut self, market: usize, token: String, kind: GoodKind) -> bool {
+        match self.markets.buy_or_partial(market, &self.name, token, &mut self.goods[0]) {
+            Ok(good) => {
+                let received = good.get_qty();
+                let _ = self.goods[get_index_by_goodkind(&kind)].merge(good);
+                self.filled(TraderFill { side: Side::Buy, market, kind, received });
+                self.counts.buy += 1;
+                true
+            }
+            Err(error) => {
+                self.refused(TradeOp::Buy, market, &error);
+                self.send(TraderEvent::FillFailed { trader: self.id, day: self.day(), side: Side::Buy, market, kind, error: (&error).into() });
+                false
+            }
+        }
+    }
+
+    /// Delivers the good of a sell lock, taking a partial fill where the market has one, and reports the new portfolio.
+    pub fn sell(&mut self, market: usize, token: String, kind: GoodKind) -> bool {
+        let index = get_index_by_goodkind(&kind);
+        match self.markets.sell_or_partial(market, &self.name, token, &mut self.goods[index]) {
+            Ok(eur) => {
+                let received = eur.get_qty();
+                let _ = self.goods[0].merge(eur);
+                self.filled(TraderFill { side: Side::Sell, market, kind, received });
+                self.counts.sell += 1;
+                true
+            }
+            Err(error) => {
+                self.refused(TradeOp::Sell, market, &error);
+                self.send(TraderEvent::FillFailed { trader: self.id, day: self.day(), side: Side::Sell, market, kind, error: (&error).into() });
+                false
+            }
+        }
+    }
+
+    /// Gives a lock back, ignored by markets that can't cancel.
+    pub fn cancel_lock(&self, market: usize, token: &str) {
+        let _ = self.markets.cancel_lock(market, token, &self.name);
+    }
+
+    /// Tells the sinks we gave up on a lock we never executed, whether or not the market still holds it.
+    pub fn expired(&self, side: Side, market: usize, token: &str) {
+        self.send(TraderEvent::Expired { trader: self.id, day: self.day(), side, market, token: token.to_string() });
+    }
+
+    //what the markets' circuit breakers did since the last tick
+    fn relay_breakers(&self) {
+        for (market, event) in self.markets.breaker_events() {
+            let (trader, day) = (self.id, self.day());
+            match event {
+                BreakerEvent::Halted { reason, days } => self.send(TraderEvent::MarketHalted { trader, day, market, reason, days }),
+                BreakerEvent::Resumed => self.send(TraderEvent::MarketResumed { trader, day, market }),
+            }
+        }
+    }
+
+    fn filled(&mut self, fill: TraderFill) {
+        self.send(TraderEvent::Filled { trader: self.id, day: self.day(), fill: fill.clone() });
+        self.fills.push(fill);
+        self.report();
+    }
+
+    fn refused(&mut self, op: TradeOp, market: usize, error: &impl Debug) {
+        self.errors.push(TraderError { op, market, error: format!("{:?}", error) });
+        self.counts.refused += 1;
+    }
+
+    fn send(&self, event: TraderEvent) {
+        if let Some(tx) = &self.tx {
+            //nobody listening anymore is not our problem
+            let _ = tx.send(event);
+        }
+    }
+
+    //EUR value of every good, once per fill
+    fn report(&self) {
+        if self.tx.is_none() {
+            return;
+        }
+        let h = self.holdings_eur();
+        self.send(TraderEvent::PortfolioUpdate { trader: self.id, day: self.day(), values: [h[0], h[1], h[2], h[3]] });
+        std::thread::sleep(std::time::Duration::from_millis(self.delay_ms));
+    }
+}
+
+impl Trader {
+    //data holds our starting EUR USD YEN YUAN valued in EUR, markets[i] the goods of the i-th venue,
+    //zse configures our ZSE, seed drives the strategy and our markets' tokens
+    pub fn new_with_quantities(strategy: Box<dyn TraderStrategy>, id: u32, delay_ms: u64, data: Vec<f32>, markets: Vec<Vec<f32>>, zse: &ZseConfig, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let registry = MarketRegistry::new_with_quantities(&markets, zse, &mut rng);
+        Trader::new(strategy, id, delay_ms, data, registry, rng)
+    }
+
+    /// Trades on markets made elsewhere, e.g. a registry whose ZSE was resumed from a snapshot.
+    pub fn new(strategy: Box<dyn TraderStrategy>, id: u32, delay_ms: u64, data: Vec<f32>, markets: MarketRegistry, rng: StdRng) -> Self {
+        let core = TraderCore::new(strategy.name(), id, markets, &data, delay_ms, rng);
+        Trader { core, strategy }
+    }
+
+    pub fn core(&self) -> &TraderCore {
+        &self.core
+    }
+
+    /// Runs one tick and hands what happened during it to the strategy, false once it is done.
+    pub fn step(&mut self) -> bool {
+        let running = self.strategy.on_tick(&mut self.core);
+        self.core.relay_breakers();
+        for fill in std::mem::take(&mut self.core.fills) {
+            self.strategy.on_fill(&mut self.core, &fill);
+        }
+        for error in std::mem::take(&mut self.core.errors) {
+            self.strategy.on_error(&mut self.core, &error);
+        }
+        running
+    }
+
+    pub fn trade(&mut self, tx: &Sender<TraderEvent>) {
+        self.core.tx = Some(tx.clone());
+        while self.step() {}
+
+        let core = &self.core;
+        let budget = core.get_budget();
+        if budget > 0.0 {
+            core.send(TraderEvent::Finished { trader: core.id, day: core.day(), budget });
+        } else {
+            core.send(TraderEvent::Bankrupt { trader: core.id, day: core.day() });
+        }
+    }
+}
+
+pub fn get_index_by_goodkind(kind: &GoodKind) -> usize {
+    match *kind {
+        GoodKind::EUR => 0,
+        GoodKind::USD => 1,
+        GoodKind::YEN => 2,
+        GoodKind::YUAN => 3,
+    }
+}
+
+pub fn get_goodkind_by_index(i: usize) -> GoodKind {
+    match i {
+        1 => GoodKind::USD,
+        2 => GoodKind::YEN,
+        3 => GoodKind::YUAN,
+        _ => GoodKind::EUR,
+    }
+}